@@ -0,0 +1,32 @@
+CREATE TABLE IF NOT EXISTS "discussions" (
+  "id" INTEGER NOT NULL,
+  "user_id" INTEGER NOT NULL,
+  "username" TEXT NOT NULL,
+  "user_display_name" TEXT NOT NULL,
+  "title" TEXT NOT NULL,
+  "tags" TEXT NOT NULL,
+  "is_frontpage" integer NOT NULL,
+  "created_at" TEXT NOT NULL,
+  PRIMARY KEY ("id")
+);
+
+CREATE TABLE IF NOT EXISTS "jobs" (
+  "entity" TEXT NOT NULL,
+  "entity_id" INTEGER NOT NULL,
+  "status" TEXT NOT NULL,
+  PRIMARY KEY ("entity", "entity_id")
+);
+
+CREATE TABLE IF NOT EXISTS "posts" (
+  "id" INTEGER NOT NULL,
+  "user_id" INTEGER NOT NULL,
+  "discussion_id" INTEGER NOT NULL,
+  "reply_to_id" INTEGER NOT NULL,
+  "username" TEXT NOT NULL,
+  "user_display_name" TEXT NOT NULL,
+  "content" TEXT NOT NULL,
+  "created_at" TEXT NOT NULL,
+  PRIMARY KEY ("id")
+);
+
+CREATE INDEX IF NOT EXISTS "posts_discussion_id" ON "posts" ("discussion_id");
@@ -0,0 +1,38 @@
+CREATE VIRTUAL TABLE IF NOT EXISTS "posts_fts" USING fts5(
+  "content",
+  content='posts',
+  content_rowid='id',
+  tokenize='trigram'
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS "discussions_fts" USING fts5(
+  "title",
+  content='discussions',
+  content_rowid='id',
+  tokenize='trigram'
+);
+
+CREATE TRIGGER IF NOT EXISTS "posts_fts_ai" AFTER INSERT ON "posts" BEGIN
+  INSERT INTO "posts_fts" (rowid, "content") VALUES (new."id", new."content");
+END;
+CREATE TRIGGER IF NOT EXISTS "posts_fts_ad" AFTER DELETE ON "posts" BEGIN
+  INSERT INTO "posts_fts" ("posts_fts", rowid, "content") VALUES ('delete', old."id", old."content");
+END;
+CREATE TRIGGER IF NOT EXISTS "posts_fts_au" AFTER UPDATE OF "content" ON "posts" BEGIN
+  INSERT INTO "posts_fts" ("posts_fts", rowid, "content") VALUES ('delete', old."id", old."content");
+  INSERT INTO "posts_fts" (rowid, "content") VALUES (new."id", new."content");
+END;
+
+CREATE TRIGGER IF NOT EXISTS "discussions_fts_ai" AFTER INSERT ON "discussions" BEGIN
+  INSERT INTO "discussions_fts" (rowid, "title") VALUES (new."id", new."title");
+END;
+CREATE TRIGGER IF NOT EXISTS "discussions_fts_ad" AFTER DELETE ON "discussions" BEGIN
+  INSERT INTO "discussions_fts" ("discussions_fts", rowid, "title") VALUES ('delete', old."id", old."title");
+END;
+CREATE TRIGGER IF NOT EXISTS "discussions_fts_au" AFTER UPDATE OF "title" ON "discussions" BEGIN
+  INSERT INTO "discussions_fts" ("discussions_fts", rowid, "title") VALUES ('delete', old."id", old."title");
+  INSERT INTO "discussions_fts" (rowid, "title") VALUES (new."id", new."title");
+END;
+
+INSERT INTO "posts_fts" ("posts_fts") VALUES ('rebuild');
+INSERT INTO "discussions_fts" ("discussions_fts") VALUES ('rebuild');
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS "embeddings" (
+  "post_id" INTEGER NOT NULL,
+  "model" TEXT NOT NULL,
+  "chunk_index" INTEGER NOT NULL,
+  "content_hash" TEXT NOT NULL,
+  "content" TEXT NOT NULL,
+  "vector" BLOB NOT NULL,
+  "created_at" TEXT NOT NULL,
+  PRIMARY KEY ("post_id", "model", "chunk_index")
+);
+
+CREATE INDEX IF NOT EXISTS "embeddings_model" ON "embeddings" ("model");
@@ -0,0 +1,21 @@
+ALTER TABLE "posts" ADD COLUMN "edited_at" TEXT;
+ALTER TABLE "posts" ADD COLUMN "edited_user_id" INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS "post_revisions" (
+  "id" INTEGER NOT NULL,
+  "post_id" INTEGER NOT NULL,
+  "content" TEXT NOT NULL,
+  "edited_at" TEXT,
+  "edited_user_id" INTEGER NOT NULL,
+  "replaced_at" TEXT NOT NULL,
+  PRIMARY KEY ("id" AUTOINCREMENT)
+);
+
+CREATE INDEX IF NOT EXISTS "post_revisions_post_id" ON "post_revisions" ("post_id");
+
+-- Keep the previous version whenever a re-crawl overwrites a post with different content
+CREATE TRIGGER IF NOT EXISTS "posts_revision_au" AFTER UPDATE OF "content" ON "posts"
+WHEN old."content" IS NOT new."content" BEGIN
+  INSERT INTO "post_revisions" ("post_id", "content", "edited_at", "edited_user_id", "replaced_at")
+  VALUES (old."id", old."content", old."edited_at", old."edited_user_id", strftime('%Y-%m-%dT%H:%M:%SZ', 'now'));
+END;
@@ -0,0 +1,9 @@
+-- deleted_at / hidden_at come from Flarum when it exposes them, otherwise from the time a re-crawl
+-- noticed the content was gone; removal_detected_at is only set by such a detection.
+ALTER TABLE "discussions" ADD COLUMN "deleted_at" TEXT;
+ALTER TABLE "discussions" ADD COLUMN "hidden_at" TEXT;
+ALTER TABLE "discussions" ADD COLUMN "removal_detected_at" TEXT;
+
+ALTER TABLE "posts" ADD COLUMN "deleted_at" TEXT;
+ALTER TABLE "posts" ADD COLUMN "hidden_at" TEXT;
+ALTER TABLE "posts" ADD COLUMN "removal_detected_at" TEXT;
@@ -0,0 +1,7 @@
+-- Small pieces of crawler state, e.g. login tokens and checkpoints
+CREATE TABLE IF NOT EXISTS "kv" (
+  "key" TEXT NOT NULL,
+  "value" TEXT NOT NULL,
+  "updated_at" TEXT NOT NULL,
+  PRIMARY KEY ("key")
+);
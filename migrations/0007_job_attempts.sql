@@ -0,0 +1,7 @@
+-- attempts counts the tries since the job last succeeded or became impossible. Jobs recorded before
+-- this migration have no timestamps.
+ALTER TABLE "jobs" ADD COLUMN "attempts" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "jobs" ADD COLUMN "first_seen_at" TEXT;
+ALTER TABLE "jobs" ADD COLUMN "last_attempt_at" TEXT;
+ALTER TABLE "jobs" ADD COLUMN "next_eligible_at" TEXT;
+ALTER TABLE "jobs" ADD COLUMN "last_error" TEXT;
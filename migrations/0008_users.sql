@@ -0,0 +1,16 @@
+-- Filled from the users included in API responses and from /api/users. Fields Flarum does not expose
+-- to the crawler's account stay NULL.
+CREATE TABLE IF NOT EXISTS "users" (
+  "id" INTEGER NOT NULL,
+  "username" TEXT NOT NULL,
+  "display_name" TEXT NOT NULL,
+  "avatar_url" TEXT,
+  "bio" TEXT,
+  "joined_at" TEXT,
+  "last_seen_at" TEXT,
+  "comment_count" INTEGER,
+  "discussion_count" INTEGER,
+  "group_ids" TEXT,
+  "updated_at" TEXT NOT NULL,
+  PRIMARY KEY ("id")
+);
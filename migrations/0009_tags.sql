@@ -0,0 +1,20 @@
+-- Filled from /api/tags and from the tags included with each discussion. Discussions crawled before
+-- this migration get their discussion_tags rows on the next crawl.
+CREATE TABLE IF NOT EXISTS "tags" (
+  "id" INTEGER NOT NULL,
+  "name" TEXT NOT NULL,
+  "slug" TEXT NOT NULL,
+  "color" TEXT,
+  "description" TEXT,
+  "parent_id" INTEGER,
+  "position" INTEGER,
+  "updated_at" TEXT NOT NULL,
+  PRIMARY KEY ("id")
+);
+
+CREATE TABLE IF NOT EXISTS "discussion_tags" (
+  "discussion_id" INTEGER NOT NULL,
+  "tag_id" INTEGER NOT NULL,
+  PRIMARY KEY ("discussion_id", "tag_id")
+);
+CREATE INDEX IF NOT EXISTS "discussion_tags_tag_id" ON "discussion_tags" ("tag_id");
@@ -0,0 +1,19 @@
+-- Discussion attributes as returned by Flarum. Rows crawled before this migration get their activity
+-- from the stored posts until the next crawl.
+ALTER TABLE "discussions" ADD COLUMN "slug" TEXT NOT NULL DEFAULT '';
+ALTER TABLE "discussions" ADD COLUMN "comment_count" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "discussions" ADD COLUMN "participant_count" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "discussions" ADD COLUMN "last_posted_at" TEXT;
+ALTER TABLE "discussions" ADD COLUMN "last_post_number" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "discussions" ADD COLUMN "is_sticky" integer NOT NULL DEFAULT 0;
+ALTER TABLE "discussions" ADD COLUMN "is_locked" integer NOT NULL DEFAULT 0;
+ALTER TABLE "discussions" ADD COLUMN "is_approved" integer NOT NULL DEFAULT 1;
+
+UPDATE "discussions" SET
+  "comment_count" = (SELECT count(*) FROM "posts" p WHERE p."discussion_id" = "discussions"."id"),
+  "last_posted_at" = COALESCE(
+    (SELECT max(p."created_at") FROM "posts" p WHERE p."discussion_id" = "discussions"."id"),
+    "created_at"
+  );
+
+CREATE INDEX IF NOT EXISTS "discussions_last_posted_at" ON "discussions" ("last_posted_at");
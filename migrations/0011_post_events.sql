@@ -0,0 +1,5 @@
+-- number is the post's position in its discussion (/d/{discussion_id}/{number}). Event posts, such as
+-- discussionRenamed or discussionTagged, have an event_type and Flarum's JSON payload, and empty content.
+ALTER TABLE "posts" ADD COLUMN "number" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "posts" ADD COLUMN "event_type" TEXT;
+ALTER TABLE "posts" ADD COLUMN "event_payload" TEXT;
@@ -0,0 +1,15 @@
+-- The raw contentHtml that content is converted from, and the source text when the API exposes it
+ALTER TABLE "posts" ADD COLUMN "content_html" TEXT;
+ALTER TABLE "posts" ADD COLUMN "source" TEXT;
+ALTER TABLE "post_revisions" ADD COLUMN "content_html" TEXT;
+ALTER TABLE "post_revisions" ADD COLUMN "source" TEXT;
+
+-- Once the HTML is stored, only a change of it is an edit. A new conversion of the same HTML, e.g. by
+-- `reconvert`, does not create a revision.
+DROP TRIGGER IF EXISTS "posts_revision_au";
+CREATE TRIGGER "posts_revision_au" AFTER UPDATE OF "content", "content_html" ON "posts"
+WHEN CASE WHEN old."content_html" IS NULL THEN old."content" IS NOT new."content"
+  ELSE old."content_html" IS NOT new."content_html" END BEGIN
+  INSERT INTO "post_revisions" ("post_id", "content", "content_html", "source", "edited_at", "edited_user_id", "replaced_at")
+  VALUES (old."id", old."content", old."content_html", old."source", old."edited_at", old."edited_user_id", strftime('%Y-%m-%dT%H:%M:%SZ', 'now'));
+END;
@@ -0,0 +1,11 @@
+-- Every PostMention ("post") and UserMention ("user") found in a post's HTML
+CREATE TABLE IF NOT EXISTS "post_mentions" (
+  "post_id" INTEGER NOT NULL,
+  "kind" TEXT NOT NULL,
+  "mentioned_id" INTEGER NOT NULL,
+  PRIMARY KEY ("post_id", "kind", "mentioned_id")
+);
+CREATE INDEX IF NOT EXISTS "post_mentions_mentioned" ON "post_mentions" ("kind", "mentioned_id");
+
+INSERT OR IGNORE INTO "post_mentions" ("post_id", "kind", "mentioned_id")
+SELECT "id", 'post', "reply_to_id" FROM "posts" WHERE "reply_to_id" != 0;
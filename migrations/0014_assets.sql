@@ -0,0 +1,17 @@
+-- Images and attachments referenced by posts, mirrored into a content-addressed store. path is relative to
+-- the configured assets dir and NULL when the download failed or was not allowed, see error.
+CREATE TABLE IF NOT EXISTS "assets" (
+  "url" TEXT PRIMARY KEY NOT NULL,
+  "sha256" TEXT,
+  "path" TEXT,
+  "content_type" TEXT,
+  "size" INTEGER,
+  "error" TEXT,
+  "fetched_at" TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS "post_assets" (
+  "post_id" INTEGER NOT NULL,
+  "url" TEXT NOT NULL,
+  PRIMARY KEY ("post_id", "url")
+);
+CREATE INDEX IF NOT EXISTS "post_assets_url" ON "post_assets" ("url");
@@ -0,0 +1,35 @@
+-- When anything exported about a discussion last changed, for `export --since-last-export`. Crawls
+-- re-save unchanged discussions and posts, so only actual differences bump it.
+ALTER TABLE "discussions" ADD COLUMN "changed_at" TEXT;
+UPDATE "discussions" SET "changed_at" = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+CREATE INDEX IF NOT EXISTS "discussions_changed_at" ON "discussions" ("changed_at");
+
+CREATE TRIGGER IF NOT EXISTS "discussions_changed_ai" AFTER INSERT ON "discussions" BEGIN
+  UPDATE "discussions" SET "changed_at" = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE "id" = new."id";
+END;
+CREATE TRIGGER IF NOT EXISTS "discussions_changed_au" AFTER UPDATE ON "discussions"
+WHEN (old."user_id", old."username", old."user_display_name", old."title", old."tags", old."is_frontpage",
+  old."created_at", old."deleted_at", old."hidden_at", old."slug", old."comment_count", old."participant_count",
+  old."last_posted_at", old."last_post_number", old."is_sticky", old."is_locked", old."is_approved")
+  IS NOT (new."user_id", new."username", new."user_display_name", new."title", new."tags", new."is_frontpage",
+  new."created_at", new."deleted_at", new."hidden_at", new."slug", new."comment_count", new."participant_count",
+  new."last_posted_at", new."last_post_number", new."is_sticky", new."is_locked", new."is_approved") BEGIN
+  UPDATE "discussions" SET "changed_at" = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE "id" = new."id";
+END;
+
+CREATE TRIGGER IF NOT EXISTS "posts_changed_ai" AFTER INSERT ON "posts" BEGIN
+  UPDATE "discussions" SET "changed_at" = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE "id" = new."discussion_id";
+END;
+CREATE TRIGGER IF NOT EXISTS "posts_changed_au" AFTER UPDATE ON "posts"
+WHEN (old."discussion_id", old."user_id", old."reply_to_id", old."username", old."user_display_name", old."content",
+  old."created_at", old."edited_at", old."deleted_at", old."hidden_at", old."number", old."event_type",
+  old."event_payload")
+  IS NOT (new."discussion_id", new."user_id", new."reply_to_id", new."username", new."user_display_name", new."content",
+  new."created_at", new."edited_at", new."deleted_at", new."hidden_at", new."number", new."event_type",
+  new."event_payload") BEGIN
+  UPDATE "discussions" SET "changed_at" = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+  WHERE "id" IN (old."discussion_id", new."discussion_id");
+END;
+CREATE TRIGGER IF NOT EXISTS "posts_changed_ad" AFTER DELETE ON "posts" BEGIN
+  UPDATE "discussions" SET "changed_at" = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE "id" = old."discussion_id";
+END;
@@ -0,0 +1,13 @@
+-- 0015 left content_html and source out of the posts update trigger, so a changed HTML or source that
+-- converts to the same Markdown did not bump changed_at.
+DROP TRIGGER IF EXISTS "posts_changed_au";
+CREATE TRIGGER IF NOT EXISTS "posts_changed_au" AFTER UPDATE ON "posts"
+WHEN (old."discussion_id", old."user_id", old."reply_to_id", old."username", old."user_display_name", old."content",
+  old."created_at", old."edited_at", old."deleted_at", old."hidden_at", old."number", old."event_type",
+  old."event_payload", old."content_html", old."source")
+  IS NOT (new."discussion_id", new."user_id", new."reply_to_id", new."username", new."user_display_name", new."content",
+  new."created_at", new."edited_at", new."deleted_at", new."hidden_at", new."number", new."event_type",
+  new."event_payload", new."content_html", new."source") BEGIN
+  UPDATE "discussions" SET "changed_at" = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+  WHERE "id" IN (old."discussion_id", new."discussion_id");
+END;
@@ -1,20 +1,29 @@
-use crate::entity::{Discussion, Post};
-use anyhow::{Context, anyhow, bail};
+use crate::auth::Auth;
+use crate::entity::{Discussion, Post, Tag, User};
+use crate::jsonapi::{
+    DiscussionAttributes, DiscussionListAttributes, Document, JsonApiError, PostAttributes,
+    PostEditAttributes, Resource, TagAttributes, UserAttributes,
+};
+use crate::throttle::{get_throttle, is_transient_status, parse_retry_after};
+use anyhow::bail;
+use chrono::{FixedOffset, Utc};
 use derive_builder::Builder;
+use itertools::Itertools;
 use regex::Regex;
-use reqwest::{Client, StatusCode};
-use std::collections::{HashMap, HashSet};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Client, Response, StatusCode};
+use std::collections::HashMap;
 use std::sync::{Arc, LazyLock};
 use std::time::Duration;
-use thiserror::Error;
 use tokio::sync::Semaphore;
 use tokio::task::JoinSet;
-use tracing::{debug, info, instrument};
+use tokio::time::sleep;
+use tracing::{debug, instrument, warn};
 
 pub enum GetDiscussionResult {
-    Impossible,
+    Impossible(StatusCode),
     Ok(Discussion),
-    PartialError(Discussion),
+    PartialError(Discussion, String), // with the error of the first failed post group
 }
 
 #[derive(Debug, Clone, Builder)]
@@ -22,8 +31,12 @@ pub struct GetDiscussionOptions {
     pub base_url: String,
     #[builder(default = 20)]
     pub concurrency: usize,
-    #[builder(default=HashSet::new())]
-    pub existing_post_ids: HashSet<u64>,
+    /// Stored post id -> `edited_at`. These posts are not fetched again unless the discussion
+    /// response includes them with another `editedAt`.
+    #[builder(default=HashMap::new())]
+    pub existing_posts: HashMap<u64, Option<chrono::DateTime<FixedOffset>>>,
+    #[builder(default)]
+    pub auth: Option<Arc<Auth>>,
 }
 static HTTP_CLIENT: LazyLock<Client> = LazyLock::new(|| {
     Client::builder()
@@ -31,55 +44,168 @@ static HTTP_CLIENT: LazyLock<Client> = LazyLock::new(|| {
         .build()
         .unwrap()
 });
-fn get_http_client() -> Client {
+pub fn get_http_client() -> Client {
     HTTP_CLIENT.clone()
 }
+/// Sends a GET request, pacing it per host and retrying transient errors with backoff.
+async fn send_get(url: &str, auth: Option<&Auth>) -> anyhow::Result<Response> {
+    send_get_with(&HTTP_CLIENT, url, auth).await
+}
+/// `send_get` with another client, e.g. one with its own redirect policy.
+async fn send_get_with(
+    client: &Client,
+    url: &str,
+    auth: Option<&Auth>,
+) -> anyhow::Result<Response> {
+    let throttle = get_throttle();
+    let mut attempt = 0;
+    loop {
+        throttle.acquire(url).await;
+        let result = send_get_once(client, url, auth).await;
+        let retry_after = match &result {
+            Ok(response) if is_transient_status(response.status()) => {
+                parse_retry_after(response.headers())
+            }
+            Ok(_) => {
+                throttle.record_success();
+                return result;
+            }
+            Err(err)
+                if err
+                    .downcast_ref::<reqwest::Error>()
+                    .is_some_and(|x| x.is_timeout() || x.is_connect() || x.is_request()) =>
+            {
+                None
+            }
+            Err(_) => return result,
+        };
+        if attempt >= throttle.max_retries() || !throttle.withdraw_retry() {
+            return result;
+        }
+        let delay = throttle.backoff(attempt, retry_after);
+        if let Some(retry_after) = retry_after {
+            throttle.pause_host(url, retry_after);
+        }
+        match &result {
+            Ok(response) => warn!(url, status = %response.status(), ?delay, "Retrying request"),
+            Err(err) => warn!(url, ?delay, "Retrying request: {:#}", err),
+        }
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
+/// Sends a GET request with the configured authentication, logging in again once on 401.
+async fn send_get_once(
+    client: &Client,
+    url: &str,
+    auth: Option<&Auth>,
+) -> anyhow::Result<Response> {
+    let Some(auth) = auth else {
+        return Ok(client.get(url).send().await?);
+    };
+    let header = auth.header().await?;
+    let response = client
+        .get(url)
+        .header(AUTHORIZATION, header.as_str())
+        .send()
+        .await?;
+    if response.status() != StatusCode::UNAUTHORIZED || !auth.renewable() {
+        return Ok(response);
+    }
+    warn!(url, "Token rejected, logging in again");
+    let header = auth.renew(header.as_str()).await?;
+    Ok(client.get(url).header(AUTHORIZATION, header).send().await?)
+}
+/// Downloads a file with `client` and without authentication, failing once it exceeds `max_size`
+/// bytes. Returns the body and its `Content-Type`.
+pub async fn get_asset(
+    client: &Client,
+    url: &str,
+    max_size: u64,
+) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+    let mut response = send_get_with(client, url, None).await?.error_for_status()?;
+    if response.content_length().is_some_and(|x| x > max_size) {
+        bail!("larger than {} bytes", max_size);
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.to_string());
+    let mut body = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > max_size {
+            bail!("larger than {} bytes", max_size);
+        }
+    }
+    Ok((body, content_type))
+}
+/// A discussion as listed on an index page.
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub id: u64,
+    pub last_posted_at: Option<chrono::DateTime<FixedOffset>>,
+    pub comment_count: u64,
+    pub is_sticky: bool,
+}
+/// A page of `/api/discussions`, with the users included for the authors and last posters.
+pub struct IndexPage {
+    pub entries: Vec<IndexEntry>,
+    pub users: Vec<User>,
+}
 pub async fn get_index_page(
     base_url: &str,
     page: usize,
     sort: Option<&str>,
-) -> anyhow::Result<Vec<u64>> {
+    auth: Option<&Auth>,
+) -> anyhow::Result<IndexPage> {
     let sort = sort.unwrap_or("");
-    let client = get_http_client();
     debug!(page, "Getting index page");
-    let response = client
-        .get(format!(
+    let response = send_get(
+        format!(
             "{}/api/discussions?\
     include=user,lastPostedUser,tags,tags.parent,firstPost,recipientUsers,recipientGroups&sort={}\
     &page[offset]={}",
             base_url,
             sort,
             (page - 1) * 20
-        ))
-        .send()
-        .await?;
+        )
+        .as_str(),
+        auth,
+    )
+    .await?;
     let response = match response.error_for_status() {
         Ok(response) => response,
         Err(err) => {
             bail!("response error status: {}", err);
         }
     };
-    let payload: serde_json::Value = response.json().await?;
-    let vec = vec![];
-    let ids = payload["data"]
-        .as_array()
-        .unwrap_or(&vec)
+    let document: Document<Vec<Resource>> = Document::parse(&response.bytes().await?)?;
+    let entries = document
+        .data
         .iter()
-        .filter_map(|x| {
-            if x["type"] != "discussions" {
-                return None;
-            }
-            Some(
-                x["id"]
-                    .as_str()
-                    .unwrap_or_default()
-                    .parse::<u64>()
-                    .unwrap_or_default(),
-            )
+        .filter(|x| x.kind == "discussions")
+        .map(|x| {
+            let attributes: DiscussionListAttributes = x.attributes()?;
+            Ok(IndexEntry {
+                id: x.id_u64()?,
+                last_posted_at: attributes.last_posted_at,
+                comment_count: attributes.comment_count,
+                is_sticky: attributes.is_sticky,
+            })
         })
-        .collect::<Vec<_>>();
-    debug!(len = ids.len(), page, "Got ids from index page");
-    Ok(ids)
+        .collect::<Result<Vec<_>, JsonApiError>>()?;
+    debug!(
+        len = entries.len(),
+        page,
+        has_next = document.links.next.is_some(),
+        "Got entries from index page"
+    );
+    Ok(IndexPage {
+        entries,
+        users: get_users_map(&document)?.into_values().collect(),
+    })
 }
 #[instrument(skip_all)]
 pub async fn get_discussion(
@@ -89,18 +215,23 @@ pub async fn get_discussion(
 ) -> anyhow::Result<GetDiscussionResult> {
     let sem = sem.unwrap_or_else(|| Arc::new(Semaphore::new(options.concurrency)));
     let base_url = Arc::new(options.base_url.to_string());
-    let client = get_http_client();
     let sem_quota = sem.acquire().await?;
     debug!(id, "Processing api/discussion");
-    let response = client
-        .get(format!(
-            "{base_url}/api/discussions/{id}?bySlug=true&page[near]=0",
-        ))
-        .send()
-        .await?;
+    let url = format!("{base_url}/api/discussions/{id}?bySlug=true&page[near]=0");
+    let mut response = send_get(url.as_str(), options.auth.as_deref()).await?;
+    if response.status() == StatusCode::FORBIDDEN
+        && let Some(auth) = options.auth.as_deref()
+        && auth.renewable()
+        && !auth.logged_in_recently()
+    {
+        // a stale session can look like missing permission, so only trust a 403 with a fresh login
+        warn!(id, "Forbidden, logging in again");
+        auth.renew(auth.header().await?.as_str()).await?;
+        response = send_get(url.as_str(), Some(auth)).await?;
+    }
     debug!(id, "Finished api/discussion");
-    if [404u16, 403u16].contains(&response.status().as_u16()) {
-        return Ok(GetDiscussionResult::Impossible);
+    if [StatusCode::NOT_FOUND, StatusCode::FORBIDDEN].contains(&response.status()) {
+        return Ok(GetDiscussionResult::Impossible(response.status()));
     }
     let response = match response.error_for_status() {
         Ok(response) => response,
@@ -108,82 +239,63 @@ pub async fn get_discussion(
             bail!("response error status: {}", err);
         }
     };
-    let discussion_json: serde_json::Value = response.json().await?;
+    let document: Document<Resource> = Document::parse(&response.bytes().await?)?;
     drop(sem_quota);
-    let title = discussion_json["data"]["attributes"]["title"]
-        .as_str()
-        .context("no title")?
-        .to_string();
-    let is_frontpage = discussion_json["data"]["attributes"]["frontpage"]
-        .as_bool()
-        .context("cannot get frontpage")?;
-    let vec = vec![];
-    let tag_ids = discussion_json["data"]["relationships"]["tags"]["data"]
-        .as_array()
-        .unwrap_or(&vec)
+    let attributes: DiscussionAttributes = document.data.attributes()?;
+    let tag_ids = document
+        .data
+        .to_many("tags")
         .iter()
-        .map(|x| x["id"].as_str().unwrap_or_default())
-        .collect::<Vec<_>>();
-    let tags = discussion_json["included"]
-        .as_array()
-        .unwrap_or(&vec)
+        .map(|x| x.id_u64())
+        .collect::<Result<Vec<_>, JsonApiError>>()?;
+    let included_tags = document
+        .included_of("tags")
+        .map(parse_tag)
+        .collect::<Result<Vec<_>, JsonApiError>>()?;
+    let tags = document
+        .data
+        .to_many("tags")
         .iter()
-        .filter_map(|x| {
-            if x["type"].as_str().unwrap_or_default() != "tags" {
-                return None;
-            }
-            if !tag_ids.contains(&x["id"].as_str().unwrap_or_default()) {
-                return None;
-            }
-            Some(
-                x["attributes"]["name"]
-                    .as_str()
-                    .unwrap_or_default()
-                    .to_string(),
-            )
-        })
-        .collect::<Vec<_>>();
-    let post_ids = discussion_json["data"]["relationships"]["posts"]["data"]
-        .as_array()
-        .unwrap_or(&vec)
+        .filter_map(|x| document.resolve(x))
+        .map(|x| Ok(x.attributes::<TagAttributes>()?.name))
+        .collect::<Result<Vec<_>, JsonApiError>>()?;
+    let all_post_ids = document
+        .data
+        .to_many("posts")
         .iter()
-        .filter_map(|item| {
-            if item["type"].as_str().unwrap_or_default() == "posts" {
-                let post_id = item["id"].as_str().unwrap_or_default().to_string();
-                if options
-                    .existing_post_ids
-                    .contains(&post_id.parse::<u64>().unwrap_or_default())
-                {
-                    return None;
-                }
-                Some(post_id)
-            } else {
-                None
-            }
+        .filter(|x| x.kind == "posts")
+        .map(|x| x.id_u64())
+        .collect::<Result<Vec<u64>, JsonApiError>>()?;
+    // Flarum includes the first page of posts, enough to notice edits there without refetching
+    let included_edited_at = document
+        .included_of("posts")
+        .map(|x| Ok((x.id_u64()?, x.attributes::<PostEditAttributes>()?.edited_at)))
+        .collect::<Result<HashMap<_, _>, JsonApiError>>()?;
+    let post_ids = all_post_ids
+        .iter()
+        .filter(|x| match options.existing_posts.get(x) {
+            Some(stored) => included_edited_at
+                .get(x)
+                .is_some_and(|edited_at| edited_at != stored),
+            None => true,
         })
+        .map(|x| x.to_string())
         .collect::<Vec<String>>();
-    let users_map = get_users_map(&discussion_json["included"]);
-    let user_id = discussion_json["data"]["relationships"]["user"]["data"]["id"]
-        .as_str()
-        .unwrap_or_default()
-        .parse::<u64>()?;
-    let (username, user_display_name) = users_map
+    let mut users = get_users_map(&document)?;
+    let user_id = document.data.to_one_id("user")?;
+    let (username, user_display_name) = users
         .get(&user_id)
-        .map(|x| (x.0.to_string(), x.1.to_string()))
+        .map(|x| (x.username.to_string(), x.display_name.to_string()))
         .unwrap_or_default();
-    let created_at = chrono::DateTime::parse_from_rfc3339(
-        discussion_json["data"]["attributes"]["createdAt"]
-            .as_str()
-            .unwrap_or_default(),
-    )?;
     let total = (post_ids.len() as f64 / 20f64).ceil() as usize;
     let mut set = JoinSet::new();
     let mut post_id_group_count = 0;
-    let mut is_partial = false;
+    let mut partial_error = None;
     let posts = if !post_ids.is_empty() {
         for (ix, post_id_group) in post_ids.chunks(20).map(|x| x.to_vec()).enumerate() {
             let sem_clone = sem.clone();
             let base_url = base_url.clone();
+            let auth = options.auth.clone();
             post_id_group_count += 1;
             set.spawn(async move {
                 let _sem = sem_clone.acquire().await.unwrap();
@@ -193,7 +305,8 @@ pub async fn get_discussion(
                     discussion = id,
                     "Processing api/post chunks"
                 );
-                let res = get_post_id_group(id, base_url.as_str(), post_id_group).await?;
+                let res = get_post_id_group(id, base_url.as_str(), post_id_group, auth.as_deref())
+                    .await?;
                 debug!(
                     current = ix + 1,
                     total,
@@ -203,15 +316,27 @@ pub async fn get_discussion(
                 Ok(res)
             });
         }
-        let mut post_groups = set
+        let (mut post_groups, errors): (Vec<_>, Vec<_>) = set
             .join_all()
             .await
             .into_iter()
-            .filter_map(|x: anyhow::Result<Vec<Post>>| x.ok())
-            .collect::<Vec<_>>();
-        post_groups.sort_by_key(|x| x.first().map_or(0, |x| x.id));
-        is_partial = post_groups.len() != post_id_group_count;
-        post_groups.into_iter().flatten().collect::<Vec<_>>()
+            .partition_result::<Vec<(Vec<Post>, HashMap<u64, User>)>, Vec<anyhow::Error>, _, _>();
+        post_groups.sort_by_key(|x| x.0.first().map_or(0, |x| x.id));
+        if let Some(err) = errors.first() {
+            partial_error = Some(format!(
+                "{} of {} post groups failed: {:#}",
+                errors.len(),
+                post_id_group_count,
+                err
+            ));
+        }
+        post_groups
+            .into_iter()
+            .flat_map(|(posts, group_users)| {
+                users.extend(group_users);
+                posts
+            })
+            .collect::<Vec<_>>()
     } else {
         vec![]
     };
@@ -220,14 +345,28 @@ pub async fn get_discussion(
         user_id,
         username,
         user_display_name,
-        title,
+        title: attributes.title,
         tags,
-        is_frontpage,
-        created_at,
+        is_frontpage: attributes.frontpage,
+        created_at: attributes.created_at,
         posts,
+        hidden_at: attributes.hidden_at,
+        slug: attributes.slug,
+        comment_count: attributes.comment_count,
+        participant_count: attributes.participant_count,
+        last_posted_at: attributes.last_posted_at,
+        last_post_number: attributes.last_post_number.unwrap_or_default(),
+        is_sticky: attributes.is_sticky,
+        is_locked: attributes.is_locked,
+        is_approved: attributes.is_approved,
+        post_ids: all_post_ids,
+        users: users.into_values().collect(),
+        tag_ids,
+        included_tags,
+        ..Default::default()
     };
-    if is_partial {
-        Ok(GetDiscussionResult::PartialError(discussion))
+    if let Some(err) = partial_error {
+        Ok(GetDiscussionResult::PartialError(discussion, err))
     } else {
         Ok(GetDiscussionResult::Ok(discussion))
     }
@@ -236,116 +375,226 @@ pub async fn get_discussion(
 static POST_MENTION_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r#"class="PostMention" data-id="(\d+)""#).unwrap());
 static POST_MENTION_A_RE: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"<a.*?class="PostMention".*?</a>"#).unwrap());
+    LazyLock::new(|| Regex::new(r#"<a [^>]*class="PostMention"[^>]*>.*?</a>"#).unwrap());
 static POST_MENTION_DELETED: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r#"<span class="PostMention PostMention--deleted".*?</span>"#).unwrap()
 });
+static USER_MENTION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<a [^>]*class="UserMention"[^>]*>"#).unwrap());
+static DATA_ID_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"data-id="(\d+)""#).unwrap());
+static PROFILE_SLUG_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"href="[^"]*/u/([^"/?#]+)""#).unwrap());
+#[derive(Debug, Clone, Default)]
+pub struct ConvertedHtml {
+    pub content: String,
+    pub mentioned_post_ids: Vec<u64>,
+    pub mentioned_user_ids: Vec<u64>,
+}
+/// Converts a post's `contentHtml` to Markdown and collects every post and user it mentions.
+///
+/// User mentions carry the profile slug, and on some Flarum versions a `data-id`; slugs are resolved
+/// with `user_ids_by_slug`, unknown ones are dropped.
+pub fn convert_post_html(html: &str, user_ids_by_slug: &HashMap<String, u64>) -> ConvertedHtml {
+    let html = POST_MENTION_DELETED.replace_all(html, "").to_string();
+    let mentioned_post_ids = POST_MENTION_RE
+        .captures_iter(html.as_str())
+        .filter_map(|caps| caps[1].parse::<u64>().ok())
+        .unique()
+        .collect();
+    let mentioned_user_ids = USER_MENTION_RE
+        .find_iter(html.as_str())
+        .filter_map(|tag| {
+            if let Some(caps) = DATA_ID_RE.captures(tag.as_str()) {
+                return caps[1].parse::<u64>().ok();
+            }
+            let caps = PROFILE_SLUG_RE.captures(tag.as_str())?;
+            user_ids_by_slug.get(&caps[1]).copied()
+        })
+        .unique()
+        .collect();
+    let content = htmd::convert(POST_MENTION_A_RE.replace_all(html.as_str(), "").as_ref())
+        .unwrap_or(format!("<!-- HTML -->{}", html.as_str()))
+        .trim()
+        .to_string();
+    ConvertedHtml {
+        content,
+        mentioned_post_ids,
+        mentioned_user_ids,
+    }
+}
 async fn get_post_id_group(
     discussion_id: u64,
     base_url: &str,
     post_id_group: Vec<String>,
-) -> anyhow::Result<Vec<Post>> {
-    let client = get_http_client();
+    auth: Option<&Auth>,
+) -> anyhow::Result<(Vec<Post>, HashMap<u64, User>)> {
     let url = format!(
         "{}/api/posts?filter[id]={}",
         base_url,
         post_id_group.join(",")
     );
-    let response = client.get(url.as_str()).send().await?;
+    let response = send_get(url.as_str(), auth).await?;
     let response = match response.error_for_status() {
         Ok(response) => response,
         Err(err) => {
             bail!("response error status: {}", err);
         }
     };
-    let post_json: serde_json::Value = response.json().await?;
-    let vec = vec![];
-    let users = get_users_map(&post_json["included"]);
-    let posts = post_json["data"]
-        .as_array()
-        .unwrap_or(&vec)
-        .iter()
-        .filter_map(|item| {
-            if item["type"].as_str().unwrap_or_default() != "posts" {
-                return None;
-            }
-            if item["attributes"]["contentType"]
-                .as_str()
-                .unwrap_or_default()
-                != "comment"
-            {
-                return None;
-            }
-            let mut html = item["attributes"]["contentHtml"]
-                .as_str()
-                .unwrap_or_default()
-                .to_string();
-            html = POST_MENTION_DELETED.replace_all(&html, "").to_string();
-            let created_at = chrono::DateTime::parse_from_rfc3339(
-                item["attributes"]["createdAt"].as_str().unwrap_or_default(),
-            )
+    let document: Document<Vec<Resource>> = Document::parse(&response.bytes().await?)?;
+    let users = get_users_map(&document)?;
+    let user_ids_by_slug: HashMap<String, u64> = users
+        .values()
+        .map(|x| (x.username.to_string(), x.id))
+        .collect();
+    let mut posts = vec![];
+    for item in document.data.iter() {
+        if item.kind != "posts" {
+            continue;
+        }
+        let attributes: PostAttributes = item.attributes()?;
+        let user_id = item.to_one_id("user")?;
+        let (username, user_display_name) = users
+            .get(&user_id)
+            .map(|x| (x.username.to_string(), x.display_name.to_string()))
             .unwrap_or_default();
-            let reply_to_id = if let Some(caps) = POST_MENTION_RE.captures(html.as_str()) {
-                caps[1].parse::<u64>().unwrap_or_default()
-            } else {
-                0
-            };
-            let user_id = item["relationships"]["user"]["data"]["id"]
-                .as_str()
-                .unwrap_or_default()
-                .parse::<u64>()
-                .unwrap_or_default();
-            let content = htmd::convert(POST_MENTION_A_RE.replace_all(html.as_str(), "").as_ref())
-                .unwrap_or(format!("<!-- HTML -->{}", html.as_str()))
-                .trim()
-                .to_string();
-            let _user_tmp = &("".to_string(), "".to_string());
-            let user = users.get(&user_id).unwrap_or(_user_tmp);
-            Some(Post {
-                id: item["id"]
-                    .as_str()
-                    .unwrap_or_default()
-                    .parse::<u64>()
-                    .unwrap_or_default(),
-                reply_to_id,
-                user_id,
-                username: user.0.to_string(),
-                user_display_name: user.1.to_string(),
-                content,
-                created_at,
-                discussion_id,
-            })
-        })
-        .collect::<Vec<Post>>();
-    Ok(posts)
+        let mut post = Post {
+            id: item.id_u64()?,
+            user_id,
+            username,
+            user_display_name,
+            created_at: attributes.created_at,
+            discussion_id,
+            edited_at: attributes.edited_at,
+            edited_user_id: item.to_one_id("editedUser")?,
+            hidden_at: attributes.hidden_at,
+            number: attributes.number,
+            ..Default::default()
+        };
+        if attributes.content_type != "comment" {
+            post.event_type = Some(attributes.content_type);
+            post.event_payload = attributes.content;
+            posts.push(post);
+            continue;
+        }
+        let html = attributes
+            .content_html
+            .ok_or_else(|| JsonApiError::MissingAttribute {
+                kind: item.kind.to_string(),
+                id: item.id.to_string(),
+                name: "contentHtml",
+            })?;
+        let converted = convert_post_html(&html, &user_ids_by_slug);
+        post.content = converted.content;
+        post.reply_to_id = converted
+            .mentioned_post_ids
+            .first()
+            .copied()
+            .unwrap_or_default();
+        post.mentioned_post_ids = converted.mentioned_post_ids;
+        post.mentioned_user_ids = converted.mentioned_user_ids;
+        post.content_html = Some(html);
+        post.source = attributes
+            .content
+            .and_then(|x| x.as_str().map(|x| x.to_string()));
+        posts.push(post);
+    }
+    Ok((posts, users))
+}
+fn get_users_map<D>(document: &Document<D>) -> Result<HashMap<u64, User>, JsonApiError> {
+    document
+        .included_of("users")
+        .map(|x| Ok((x.id_u64()?, parse_user(x)?)))
+        .collect()
+}
+fn parse_user(resource: &Resource) -> Result<User, JsonApiError> {
+    let attributes: UserAttributes = resource.attributes()?;
+    let group_ids = if resource.has_data("groups") {
+        let ids = resource
+            .to_many("groups")
+            .iter()
+            .map(|x| x.id_u64())
+            .collect::<Result<Vec<_>, JsonApiError>>()?;
+        Some(ids)
+    } else {
+        None
+    };
+    Ok(User {
+        id: resource.id_u64()?,
+        username: attributes.username,
+        display_name: attributes.display_name,
+        avatar_url: attributes.avatar_url,
+        bio: attributes.bio,
+        joined_at: attributes.join_time,
+        last_seen_at: attributes.last_seen_at,
+        comment_count: attributes.comment_count,
+        discussion_count: attributes.discussion_count,
+        group_ids,
+        updated_at: Utc::now().fixed_offset(),
+    })
 }
-fn get_users_map(arr_v: &serde_json::Value) -> HashMap<u64, (String, String)> {
-    let vec = vec![];
-    arr_v
-        .as_array()
-        .unwrap_or(&vec)
+fn parse_tag(resource: &Resource) -> Result<Tag, JsonApiError> {
+    let attributes: TagAttributes = resource.attributes()?;
+    let parent_id = match resource.to_one("parent")? {
+        Some(parent) => Some(parent.id_u64()?),
+        None => None,
+    };
+    Ok(Tag {
+        id: resource.id_u64()?,
+        name: attributes.name,
+        slug: attributes.slug,
+        color: attributes.color.filter(|x| !x.is_empty()),
+        description: attributes.description,
+        parent_id,
+        position: attributes.position,
+        updated_at: Utc::now().fixed_offset(),
+    })
+}
+/// Gets every tag the crawler can see, with its parent.
+pub async fn get_tags(base_url: &str, auth: Option<&Auth>) -> anyhow::Result<Vec<Tag>> {
+    let response = send_get(format!("{base_url}/api/tags?include=parent").as_str(), auth).await?;
+    let response = match response.error_for_status() {
+        Ok(response) => response,
+        Err(err) => {
+            bail!("response error status: {}", err);
+        }
+    };
+    let document: Document<Vec<Resource>> = Document::parse(&response.bytes().await?)?;
+    let mut tags = document
+        .data
         .iter()
-        .filter_map(|item| {
-            if item["type"].as_str().unwrap_or_default() != "users" {
-                return None;
-            }
-            Some((
-                item["id"]
-                    .as_str()
-                    .unwrap_or_default()
-                    .parse::<u64>()
-                    .unwrap_or_default(),
-                (
-                    item["attributes"]["username"]
-                        .as_str()
-                        .unwrap_or_default()
-                        .to_string(),
-                    item["attributes"]["displayName"]
-                        .as_str()
-                        .unwrap_or_default()
-                        .to_string(),
-                ),
-            ))
-        })
-        .collect::<HashMap<u64, (String, String)>>()
+        .chain(document.included.iter())
+        .filter(|x| x.kind == "tags")
+        .map(parse_tag)
+        .collect::<Result<Vec<_>, JsonApiError>>()?;
+    tags.sort_by_key(|x| x.id);
+    tags.dedup_by_key(|x| x.id);
+    Ok(tags)
+}
+/// A page of `/api/users`, which needs the permission to view the user list.
+pub struct UsersPage {
+    pub users: Vec<User>,
+    pub next: Option<String>,
+}
+pub async fn get_users_page(url: &str, auth: Option<&Auth>) -> anyhow::Result<UsersPage> {
+    let response = send_get(url, auth).await?;
+    if [StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN].contains(&response.status()) {
+        bail!("not permitted to list users ({})", response.status());
+    }
+    let response = match response.error_for_status() {
+        Ok(response) => response,
+        Err(err) => {
+            bail!("response error status: {}", err);
+        }
+    };
+    let document: Document<Vec<Resource>> = Document::parse(&response.bytes().await?)?;
+    let users = document
+        .data
+        .iter()
+        .filter(|x| x.kind == "users")
+        .map(parse_user)
+        .collect::<Result<Vec<_>, JsonApiError>>()?;
+    Ok(UsersPage {
+        users,
+        next: document.links.next,
+    })
 }
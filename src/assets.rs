@@ -0,0 +1,179 @@
+use crate::api::get_asset;
+use crate::config::AssetsConfig;
+use crate::entity::Asset;
+use chrono::Utc;
+use itertools::Itertools;
+use regex::Regex;
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::fs::{create_dir_all, rename, try_exists, write};
+use tracing::{debug, warn};
+
+const MAX_REDIRECTS: usize = 10;
+static IMG_SRC_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<img [^>]*src="([^"]+)""#).unwrap());
+// FoF Upload stores files under /assets/files/ by default
+static ATTACHMENT_HREF_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<a [^>]*href="([^"]*/assets/files/[^"]+)""#).unwrap());
+
+/// Returns the absolute URLs of the images and attachments in a post's `contentHtml`.
+pub fn extract_asset_urls(html: &str, base_url: &str) -> Vec<String> {
+    extract_asset_links(html, base_url)
+        .into_iter()
+        .map(|(_, url)| url)
+        .unique()
+        .collect()
+}
+/// Returns the images and attachments in a post's `contentHtml` as `(src or href as written,
+/// absolute URL)`. The written form may be relative or HTML-escaped.
+pub fn extract_asset_links(html: &str, base_url: &str) -> Vec<(String, String)> {
+    let Ok(base) = Url::parse(format!("{base_url}/").as_str()) else {
+        return vec![];
+    };
+    let mut links = vec![];
+    for caps in IMG_SRC_RE
+        .captures_iter(html)
+        .chain(ATTACHMENT_HREF_RE.captures_iter(html))
+    {
+        let Ok(url) = base.join(caps[1].replace("&amp;", "&").as_str()) else {
+            continue;
+        };
+        if matches!(url.scheme(), "http" | "https") {
+            links.push((caps[1].to_string(), url.to_string()));
+        }
+    }
+    links
+}
+/// Points the Markdown links and images to the asset written as `src` in the HTML (see
+/// `extract_asset_links`) at `link`. Only link destinations are replaced, since a relative `src`
+/// may also occur in other text.
+pub fn rewrite_asset_link(content: &str, src: &str, url: &str, link: &str) -> String {
+    let mut content = content.to_string();
+    for from in [url, src, src.replace("&amp;", "&").as_str()]
+        .into_iter()
+        .unique()
+    {
+        for (open, close) in [("](", ")"), ("](", " "), ("<", ">")] {
+            content = content.replace(
+                format!("{open}{from}{close}").as_str(),
+                format!("{open}{link}{close}").as_str(),
+            );
+        }
+    }
+    content
+}
+
+/// Downloads assets into `{dir}/{sha256[..2]}/{sha256}.{ext}`, so a file linked from several posts or
+/// URLs is stored once.
+#[derive(Debug, Clone)]
+pub struct AssetStore {
+    config: AssetsConfig,
+    allowed_domains: Vec<String>,
+    client: Client, // follows redirects only to allowed domains
+}
+impl AssetStore {
+    pub fn new(config: AssetsConfig, base_url: &str) -> Self {
+        let mut allowed_domains = config.allowed_domains.clone();
+        if allowed_domains.is_empty()
+            && let Some(host) = Url::parse(base_url)
+                .ok()
+                .and_then(|x| x.host_str().map(|x| x.to_string()))
+        {
+            allowed_domains.push(host);
+        }
+        let domains = allowed_domains.clone();
+        let client = Client::builder()
+            .timeout(Duration::from_secs(60))
+            .redirect(Policy::custom(move |attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if is_allowed(&domains, attempt.url()) {
+                    attempt.follow()
+                } else {
+                    let error = format!("redirect to a domain not allowed: {}", attempt.url());
+                    attempt.error(error)
+                }
+            }))
+            .build()
+            .unwrap();
+        Self {
+            config,
+            allowed_domains,
+            client,
+        }
+    }
+    /// Downloads `url` and returns its record. Failures are recorded in `error` instead of returned.
+    pub async fn mirror(&self, url: &str) -> Asset {
+        let mut asset = Asset {
+            url: url.to_string(),
+            fetched_at: Utc::now().fixed_offset(),
+            ..Default::default()
+        };
+        if !Url::parse(url).is_ok_and(|x| is_allowed(&self.allowed_domains, &x)) {
+            asset.error = Some("domain not allowed".to_string());
+            return asset;
+        }
+        let result: anyhow::Result<()> = async {
+            let (body, content_type) = get_asset(&self.client, url, self.config.max_size).await?;
+            let sha256 = format!("{:x}", Sha256::digest(&body));
+            let path = format!("{}/{}{}", &sha256[..2], sha256, extension_of(url));
+            let full_path = Path::new(self.config.dir.as_str()).join(path.as_str());
+            if !try_exists(&full_path).await? {
+                create_dir_all(full_path.parent().unwrap()).await?;
+                // unique, as other tasks may be downloading the same file under another URL
+                let tmp_path = full_path.with_extension(format!("{:016x}.tmp", fastrand::u64(..)));
+                write(&tmp_path, &body).await?;
+                rename(&tmp_path, &full_path).await?;
+            }
+            asset.sha256 = Some(sha256);
+            asset.path = Some(path);
+            asset.content_type = content_type;
+            asset.size = Some(body.len() as u64);
+            Ok(())
+        }
+        .await;
+        match result {
+            Ok(()) => debug!(url, "Mirrored asset"),
+            Err(err) => {
+                warn!(url, "Cannot mirror asset: {:#}", err);
+                asset.error = Some(format!("{:#}", err));
+            }
+        }
+        asset
+    }
+    /// Link to a mirrored asset from a Markdown export at `export/{seg}/{id}.md`.
+    pub fn export_link(&self, path: &str) -> String {
+        if Path::new(self.config.dir.as_str()).is_absolute() {
+            format!("{}/{}", self.config.dir, path)
+        } else {
+            format!("../../{}/{}", self.config.dir, path)
+        }
+    }
+}
+fn is_allowed(allowed_domains: &[String], url: &Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    allowed_domains
+        .iter()
+        .any(|x| host == x || host.ends_with(format!(".{x}").as_str()))
+}
+/// A short alphanumeric extension taken from the URL path, e.g. `.png`, or nothing.
+fn extension_of(url: &str) -> String {
+    let path = Url::parse(url)
+        .map(|x| x.path().to_string())
+        .unwrap_or_default();
+    match Path::new(path.as_str())
+        .extension()
+        .and_then(|x| x.to_str())
+    {
+        Some(ext) if ext.len() <= 5 && ext.chars().all(|x| x.is_ascii_alphanumeric()) => {
+            format!(".{}", ext.to_ascii_lowercase())
+        }
+        _ => "".to_string(),
+    }
+}
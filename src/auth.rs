@@ -0,0 +1,112 @@
+use crate::api::get_http_client;
+use crate::config::AuthConfig;
+use crate::entity::Kv;
+use anyhow::{Context, bail};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::info;
+
+/// Provides the `Authorization` header for Flarum API requests.
+///
+/// An API key is sent as-is. With username and password, a token is obtained from `/api/token`,
+/// persisted in the `kv` table so later runs reuse it, and renewed when the forum rejects it.
+/// How long after a login a 403 is taken as missing permission rather than a stale token.
+const FRESH_LOGIN: Duration = Duration::from_secs(600);
+
+#[derive(Debug)]
+pub struct Auth {
+    base_url: String,
+    config: AuthConfig,
+    header: RwLock<Option<String>>,
+    logged_in_at: Mutex<Option<Instant>>, // of this process, unknown for a persisted token
+    conn: SqlitePool,
+}
+impl Auth {
+    pub fn new(config: AuthConfig, base_url: &str, conn: SqlitePool) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            config,
+            header: RwLock::new(None),
+            logged_in_at: Mutex::new(None),
+            conn,
+        }
+    }
+    pub fn renewable(&self) -> bool {
+        matches!(self.config, AuthConfig::Password { .. })
+    }
+    /// Whether the current token comes from a login within `FRESH_LOGIN`, so that logging in
+    /// again would not help.
+    pub fn logged_in_recently(&self) -> bool {
+        self.logged_in_at
+            .lock()
+            .unwrap()
+            .is_some_and(|x| x.elapsed() < FRESH_LOGIN)
+    }
+    fn token_key(&self, username: &str) -> String {
+        format!("auth_token:{}:{}", self.base_url, username)
+    }
+    pub async fn header(&self) -> anyhow::Result<String> {
+        if let Some(header) = self.header.read().await.clone() {
+            return Ok(header);
+        }
+        let mut guard = self.header.write().await;
+        if let Some(header) = guard.clone() {
+            return Ok(header);
+        }
+        let header = match &self.config {
+            AuthConfig::ApiKey { api_key, user_id } => format!("Token {api_key}; userId={user_id}"),
+            AuthConfig::Password { username, .. } => {
+                match Kv::get(self.token_key(username).as_str(), &self.conn).await {
+                    Some(token) => format!("Token {token}"),
+                    None => self.login().await?,
+                }
+            }
+        };
+        *guard = Some(header.clone());
+        Ok(header)
+    }
+    /// Logs in again unless another request already replaced the `stale` header or logged in
+    /// within `FRESH_LOGIN`.
+    pub async fn renew(&self, stale: &str) -> anyhow::Result<String> {
+        let mut guard = self.header.write().await;
+        if let Some(header) = guard.as_deref()
+            && (header != stale || self.logged_in_recently())
+        {
+            return Ok(header.to_string());
+        }
+        let header = self.login().await?;
+        *guard = Some(header.clone());
+        Ok(header)
+    }
+    async fn login(&self) -> anyhow::Result<String> {
+        let AuthConfig::Password { username, password } = &self.config else {
+            bail!("cannot log in with an API key");
+        };
+        info!(username, "Logging in");
+        let response = get_http_client()
+            .post(format!("{}/api/token", self.base_url))
+            .json(&json!({
+                "identification": username,
+                "password": password,
+                "remember": true,
+            }))
+            .send()
+            .await?;
+        let response = match response.error_for_status() {
+            Ok(response) => response,
+            Err(err) => {
+                bail!("login error status: {}", err);
+            }
+        };
+        let payload: serde_json::Value = response.json().await?;
+        let token = payload["token"]
+            .as_str()
+            .context("no token in login response")?;
+        Kv::set(self.token_key(username).as_str(), token, &self.conn).await;
+        *self.logged_in_at.lock().unwrap() = Some(Instant::now());
+        Ok(format!("Token {token}"))
+    }
+}
@@ -1,99 +1,407 @@
-use crate::api::get_index_page;
+use crate::api::{convert_post_html, get_index_page, get_tags, get_users_page};
+use crate::assets::{AssetStore, extract_asset_links, extract_asset_urls, rewrite_asset_link};
+use crate::auth::Auth;
 use crate::config::Config;
-use crate::crawler::Crawler;
-use crate::entity::{Discussion, Job, JobStatus};
+use crate::crawler::{Crawler, enqueue};
+use crate::embedding::{EmbeddingClient, chunk_text, content_hash};
+use crate::entity::{
+    Asset, Discussion, DiscussionCursor, Embedding, ExportFilter, Job, JobStatus, Kv, Post,
+    PostAsset, PostRevision, SearchHit, Tag, User, Vector,
+};
+use crate::export::{
+    Compression, DiscussionColumns, DiscussionTemplate, ExportArgs, ExportFormat, ExportUnit,
+    PostColumns, TemplateContext, TemplatePost, open_output, write_parquet,
+};
 use crate::server::{AppState, run_server};
+use crate::shutdown::Shutdown;
+use crate::throttle::get_throttle;
+use anyhow::Context;
+use async_channel::Receiver;
+use chrono::Utc;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
-use std::collections::HashSet;
-use std::time::Duration;
-use tokio::fs::{create_dir_all, write};
+use std::collections::{HashSet, VecDeque};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use tokio::fs::{create_dir_all, remove_file, write};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tokio::time::sleep;
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
+
+/// Progress of `full`, saved in the `kv` table for `full --resume`.
+#[derive(Debug, Serialize, Deserialize)]
+struct FullCheckpoint {
+    offset: usize, // index offset of the last page whose discussions were all crawled
+    sort: String,
+}
+const FULL_SORT: &str = "createdAt";
+/// Posts read at a time by `reconvert` and `assets`, which walk every stored post.
+const POST_BATCH_SIZE: usize = 1000;
 
 #[derive(Clone)]
 pub struct Cmd {
     config: Config,
     conn: SqlitePool,
+    auth: Option<Arc<Auth>>,
 }
 impl Cmd {
     pub fn new(config: Config, conn: SqlitePool) -> Self {
-        Self { config, conn }
+        let auth = config
+            .auth
+            .clone()
+            .map(|x| Arc::new(Auth::new(x, config.base_url.as_str(), conn.clone())));
+        Self { config, conn, auth }
+    }
+    /// Exports the discussions matching the filters of `args`, with `since_last_export` only those
+    /// that changed since the previous such run.
+    pub async fn export(&self, args: ExportArgs) -> anyhow::Result<()> {
+        let mut filter = args.filter();
+        // same format as the `changed_at` written by the triggers
+        let started_at = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        if args.since_last_export {
+            filter.changed_since = Kv::get(args.watermark_key().as_str(), &self.conn).await;
+            info!(since = ?filter.changed_since, "Exporting changed discussions");
+        }
+        match args.format {
+            ExportFormat::Markdown => self.export_markdown(args.seg_digit, &filter).await?,
+            ExportFormat::Jsonl => {
+                self.export_jsonl(&filter, args.output.as_deref(), args.per, args.compress)
+                    .await?
+            }
+            ExportFormat::Parquet => {
+                self.export_parquet(&filter, args.output.as_deref().unwrap_or("export"))
+                    .await?
+            }
+        }
+        if args.since_last_export {
+            Kv::set(
+                args.watermark_key().as_str(),
+                started_at.as_str(),
+                &self.conn,
+            )
+            .await;
+        }
+        Ok(())
     }
-    pub async fn export(&self, seg_digit: u32) {
-        let discussions = Discussion::find_all_discussions_with_posts(&self.conn).await;
+    async fn export_markdown(&self, seg_digit: u32, filter: &ExportFilter) -> anyhow::Result<()> {
+        let asset_store = match &self.config.assets {
+            Some(config) if config.rewrite_export_links => Some(AssetStore::new(
+                config.clone(),
+                self.config.base_url.as_str(),
+            )),
+            _ => None,
+        };
+        let template = DiscussionTemplate::new(&self.config.export).await?;
         create_dir_all("export").await.unwrap();
         let mut created_seg_dir: HashSet<u64> = HashSet::new();
-        for discussion in discussions.into_iter() {
+        let mut total = 0;
+        let mut cursor = DiscussionCursor::new(filter, &self.conn);
+        while let Some(mut discussion) = cursor.next().await {
+            let id = discussion.discussion.id;
+            Post::load_mentions(id, &mut discussion.posts, &self.conn).await;
+            let revisions = PostRevision::find_grouped_by_discussion_id(id, &self.conn).await;
+            if let Some(store) = &asset_store {
+                let local_paths = Asset::find_local_paths_by_discussion_id(id, &self.conn).await;
+                for post in discussion.posts.iter_mut() {
+                    let html = post.content_html.as_deref().unwrap_or_default();
+                    for (src, url) in extract_asset_links(html, self.config.base_url.as_str()) {
+                        if let Some(path) = local_paths.get(&url) {
+                            let link = store.export_link(path.as_str());
+                            post.content = rewrite_asset_link(&post.content, &src, &url, &link);
+                        }
+                    }
+                }
+            }
             let seg = discussion.discussion.id % 10u64.pow(seg_digit);
             let path = format!("export/{seg}");
             if !created_seg_dir.contains(&seg) {
                 create_dir_all(path.as_str()).await.unwrap();
                 created_seg_dir.insert(seg);
             }
-            let topic_owner_user_id = discussion
+            let user_ids = discussion
                 .posts
-                .first()
-                .cloned()
-                .unwrap_or_default()
-                .user_id;
-            let arr = discussion
-                .posts
-                .into_iter()
-                .map(|item| {
-                    let reply_line = match item.reply_to_id {
-                        0 => "".to_string(),
-                        id => {
-                            format!("In response to post id: {id}\n")
-                        }
+                .iter()
+                .flat_map(|x| std::iter::once(x.user_id).chain(x.mentioned_user_ids.clone()))
+                .unique()
+                .collect::<Vec<u64>>();
+            let context = TemplateContext {
+                base_url: self.config.base_url.as_str(),
+                discussion: &discussion.discussion,
+                posts: discussion
+                    .posts
+                    .iter()
+                    .map(|post| TemplatePost {
+                        post,
+                        event: post.event_description(),
+                        revisions: revisions
+                            .get(&post.id)
+                            .map(Vec::as_slice)
+                            .unwrap_or_default(),
+                    })
+                    .collect(),
+                users: User::find_by_ids(&user_ids, &self.conn).await,
+                tags: Tag::find_by_discussion_id(id, &self.conn).await,
+            };
+            let result = template
+                .render(&context)
+                .with_context(|| format!("failed to render discussion {id}"))?;
+            write(
+                format!("{path}/{id}.{}", self.config.export.extension),
+                result,
+            )
+            .await
+            .unwrap();
+            total += 1;
+        }
+        info!(total, skipped = cursor.skipped, "Exported discussions");
+        if !filter.include_removed {
+            // files written before the discussion was deleted or hidden
+            let mut deleted = 0;
+            for id in Discussion::find_removed_ids(filter, &self.conn).await {
+                let seg = id % 10u64.pow(seg_digit);
+                let path = format!("export/{seg}/{id}.{}", self.config.export.extension);
+                match remove_file(path.as_str()).await {
+                    Ok(()) => deleted += 1,
+                    Err(err) if err.kind() == ErrorKind::NotFound => {}
+                    Err(err) => return Err(err).with_context(|| format!("cannot delete {path}")),
+                }
+            }
+            info!(deleted, "Deleted exports of removed discussions");
+        }
+        Ok(())
+    }
+    /// Writes one JSON object per discussion, with its posts, or per post.
+    async fn export_jsonl(
+        &self,
+        filter: &ExportFilter,
+        output: Option<&str>,
+        unit: ExportUnit,
+        compression: Option<Compression>,
+    ) -> anyhow::Result<()> {
+        let mut writer = open_output(output, compression).await?;
+        let mut total = 0;
+        let mut cursor = DiscussionCursor::new(filter, &self.conn);
+        while let Some(discussion) = cursor.next().await {
+            let mut posts = discussion.posts;
+            Post::load_mentions(discussion.discussion.id, &mut posts, &self.conn).await;
+            let mut lines = match unit {
+                ExportUnit::Discussion => {
+                    let discussion = Discussion {
+                        posts,
+                        ..discussion.discussion
                     };
-                    let topic_owner_label = if topic_owner_user_id == item.user_id {
-                        " [Topic Owner]"
-                    } else {
-                        ""
-                    }
-                    .to_string();
-                    format!(
-                        "## Post ID: {}\nUser: {} (id: {}){}\n{}Created At: {}\nContent: {}\n\n---",
-                        item.id,
-                        item.user_display_name,
-                        item.user_id,
-                        topic_owner_label,
-                        reply_line,
-                        item.created_at,
-                        item.content
-                    )
-                })
-                .collect::<Vec<String>>();
-            let result = format!(
-                "# {}\n\n<{}/d/{}>\n\n===\n\n{}",
-                discussion.discussion.title,
-                self.config.base_url,
-                discussion.discussion.id,
-                arr.join("\n\n")
-            );
-            write(format!("{}/{}.md", path, discussion.discussion.id), result)
-                .await
-                .unwrap();
+                    serde_json::to_string(&discussion)?
+                }
+                ExportUnit::Post => posts
+                    .iter()
+                    .map(serde_json::to_string)
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join("\n"),
+            };
+            if lines.is_empty() {
+                continue;
+            }
+            lines.push('\n');
+            writer.write_all(lines.as_bytes()).await?;
+            total += 1;
         }
+        writer.shutdown().await?;
+        info!(total, skipped = cursor.skipped, "Exported discussions");
+        Ok(())
+    }
+    /// Writes `discussions.parquet` and `posts.parquet` into `dir`, streaming rows from the database.
+    async fn export_parquet(&self, filter: &ExportFilter, dir: &str) -> anyhow::Result<()> {
+        create_dir_all(dir).await?;
+        let total = write_parquet::<_, DiscussionColumns>(
+            format!("{dir}/discussions.parquet").as_str(),
+            Discussion::stream_by_filter(filter, &self.conn),
+        )
+        .await?;
+        info!(total, "Exported discussions");
+        let total = write_parquet::<_, PostColumns>(
+            format!("{dir}/posts.parquet").as_str(),
+            Post::stream_by_filter(filter, &self.conn),
+        )
+        .await?;
+        info!(total, "Exported posts");
+        Ok(())
     }
+    #[instrument(skip_all)]
     pub async fn embed(&self) -> anyhow::Result<()> {
-        todo!();
+        let embedding_config = self
+            .config
+            .embedding
+            .clone()
+            .context("no embedding config")?;
+        let chunk_size = embedding_config.chunk_size;
+        let batch_size = embedding_config.batch_size.max(1);
+        let client = EmbeddingClient::new(embedding_config);
+        info!(model = client.model(), "Embedding posts");
+        // posts are compared and embedded as their discussions are read, so memory use does not
+        // grow with the archive
+        let mut done = 0;
+        let mut batch = vec![];
+        let mut batch_chunks = 0;
+        let mut cursor = DiscussionCursor::new(&ExportFilter::default(), &self.conn);
+        while let Some(discussion) = cursor.next().await {
+            let post_ids = discussion.posts.iter().map(|x| x.id).collect::<Vec<_>>();
+            let hashes = Embedding::find_hashes(&post_ids, client.model(), &self.conn).await;
+            for post in discussion.posts.into_iter().filter(|x| !x.is_event()) {
+                let text = format!("{}\n\n{}", discussion.discussion.title, post.content);
+                let hash = content_hash(text.as_str());
+                if hashes.get(&post.id) == Some(&hash) {
+                    continue;
+                }
+                let chunks = chunk_text(post.content.as_str(), chunk_size)
+                    .into_iter()
+                    .map(|x| format!("{}\n\n{}", discussion.discussion.title, x))
+                    .collect::<Vec<_>>();
+                batch_chunks += chunks.len();
+                batch.push((post.id, hash, chunks));
+                if batch_chunks >= batch_size {
+                    done += self
+                        .embed_batch(&client, std::mem::take(&mut batch))
+                        .await?;
+                    batch_chunks = 0;
+                    info!(current = done, "Embedded posts");
+                }
+            }
+        }
+        done += self
+            .embed_batch(&client, std::mem::take(&mut batch))
+            .await?;
+        info!(total = done, "Embedded posts");
+        Ok(())
+    }
+    /// Embeds the chunks of `batch` in one request and replaces the embeddings of its posts.
+    /// Returns the number of posts.
+    async fn embed_batch(
+        &self,
+        client: &EmbeddingClient,
+        batch: Vec<(u64, String, Vec<String>)>,
+    ) -> anyhow::Result<usize> {
+        if batch.is_empty() {
+            return Ok(0);
+        }
+        let inputs = batch
+            .iter()
+            .flat_map(|x| x.2.iter().cloned())
+            .collect::<Vec<_>>();
+        let mut vectors = client.embed(&inputs).await?.into_iter();
+        let now = Utc::now().fixed_offset();
+        let total = batch.len();
+        for (post_id, hash, chunks) in batch {
+            let mut embeddings = chunks
+                .into_iter()
+                .enumerate()
+                .map(|(chunk_index, content)| Embedding {
+                    post_id,
+                    model: client.model().to_string(),
+                    chunk_index: chunk_index as u32,
+                    content_hash: hash.clone(),
+                    content,
+                    vector: Vector(vectors.next().unwrap_or_default()),
+                    created_at: now,
+                })
+                .collect::<Vec<_>>();
+            if embeddings.is_empty() {
+                // an empty post has nothing to embed, keep its hash so it is not retried
+                embeddings.push(Embedding {
+                    post_id,
+                    model: client.model().to_string(),
+                    content_hash: hash,
+                    created_at: now,
+                    ..Default::default()
+                });
+            }
+            Embedding::replace_for_post(post_id, client.model(), &embeddings, &self.conn).await;
+        }
+        Ok(total)
+    }
+    pub async fn search(&self, q: &str, limit: u32) -> anyhow::Result<()> {
+        let hits = SearchHit::search(q, limit, 0, false, &self.conn).await?;
+        for hit in hits {
+            println!(
+                "[{}] {} <{}/d/{}>",
+                hit.kind, hit.title, self.config.base_url, hit.discussion_id
+            );
+            if hit.post_id != 0 {
+                println!("Post ID: {}", hit.post_id);
+            }
+            println!("{}\n", hit.snippet);
+        }
         Ok(())
     }
     pub async fn server(&self, addr: String, port: u16) {
         let state = AppState {
             conn: self.conn.clone(),
             config: self.config.clone(),
+            embedding: self.config.embedding.clone().map(EmbeddingClient::new),
         };
         run_server(addr, port, state).await
     }
+    /// Crawls the first `page` index pages. With `incremental`, the index is sorted by
+    /// `-lastPostedAt`, only discussions with new activity are enqueued, and `page` is only an
+    /// upper bound: the walk stops at the first page reaching a discussion with nothing new.
     #[instrument(skip_all)]
-    pub async fn cron(&self, page: usize) -> anyhow::Result<()> {
-        let (crawler, sender) = Crawler::new(self.config.clone(), self.conn.clone()).await;
+    pub async fn cron(&self, page: usize, incremental: bool) -> anyhow::Result<()> {
+        let shutdown = Shutdown::listen();
+        if let Err(err) = self.tags().await {
+            warn!("Cannot refresh tags: {:#}", err);
+        }
+        let (crawler, sender) =
+            Crawler::new(self.config.clone(), self.conn.clone(), self.auth.clone()).await;
         let set = crawler.launch().await;
+        let sort = if incremental {
+            Some("-lastPostedAt")
+        } else {
+            None
+        };
         let mut ids = vec![];
         for i in 1..=page {
-            ids.extend(get_index_page(self.config.base_url.as_str(), i, None).await?)
+            if shutdown.is_requested() {
+                break;
+            }
+            let index_page =
+                get_index_page(self.config.base_url.as_str(), i, sort, self.auth.as_deref())
+                    .await?;
+            User::save_all(&index_page.users, &self.conn).await;
+            let entries = index_page.entries;
+            if entries.is_empty() {
+                break;
+            }
+            if !incremental {
+                ids.extend(entries.into_iter().map(|x| x.id));
+                continue;
+            }
+            let stored = Discussion::find_activity_by_ids(
+                &entries.iter().map(|x| x.id).collect::<Vec<_>>(),
+                &self.conn,
+            )
+            .await;
+            let mut reached_unchanged = false;
+            for entry in entries {
+                let changed = match stored.get(&entry.id) {
+                    None => true,
+                    Some(activity) => {
+                        entry.comment_count != activity.comment_count
+                            || entry.last_posted_at != activity.last_posted_at
+                    }
+                };
+                if changed {
+                    ids.push(entry.id);
+                } else if !entry.is_sticky {
+                    // Sticky discussions are pinned to the top regardless of activity.
+                    reached_unchanged = true;
+                }
+            }
+            info!(page = i, enqueued = ids.len(), "Processed index page");
+            if reached_unchanged {
+                break;
+            }
         }
         let len = ids.len();
         for (ix, id) in ids.into_iter().enumerate() {
@@ -103,28 +411,238 @@ impl Cmd {
                 id,
                 "Start to crawl discussion"
             );
-            sender.send(id).await?;
+            if !enqueue(&sender, id, &shutdown).await {
+                break;
+            }
         }
         drop(sender);
         set.join_all().await;
         Ok(())
     }
+    /// Re-crawls failed and partial discussions. Unless `force` is set, jobs that used up
+    /// `retry.max_attempts` or are still in their backoff window are skipped.
     #[instrument(skip_all)]
-    pub async fn retry(&self) {
+    pub async fn retry(&self, force: bool) {
+        let shutdown = Shutdown::listen();
         let mut retry_discussion_jobs =
             Job::find_by_entity_status("discussion", JobStatus::Failed, &self.conn).await;
         retry_discussion_jobs
             .extend(Job::find_by_entity_status("discussion", JobStatus::Partial, &self.conn).await);
-        let (crawler, sender) = Crawler::new(self.config.clone(), self.conn.clone()).await;
+        let total = retry_discussion_jobs.len();
+        if !force {
+            let now = Utc::now().fixed_offset();
+            retry_discussion_jobs.retain(|x| {
+                x.attempts < self.config.retry.max_attempts
+                    && x.next_eligible_at.is_none_or(|t| t <= now)
+            });
+        }
+        info!(
+            total,
+            eligible = retry_discussion_jobs.len(),
+            "Retrying discussions"
+        );
+        let (crawler, sender) =
+            Crawler::new(self.config.clone(), self.conn.clone(), self.auth.clone()).await;
         let set = crawler.launch().await;
         for job in retry_discussion_jobs {
-            sender.send(job.entity_id).await.unwrap();
+            if !enqueue(&sender, job.entity_id, &shutdown).await {
+                break;
+            }
         }
         drop(sender);
         set.join_all().await;
     }
+    /// Refreshes the `tags` table from `/api/tags`.
+    pub async fn tags(&self) -> anyhow::Result<()> {
+        let tags = get_tags(self.config.base_url.as_str(), self.auth.as_deref()).await?;
+        Tag::save_all(&tags, &self.conn).await;
+        info!(total = tags.len(), "Saved tags");
+        Ok(())
+    }
+    /// Crawls every page of `/api/users` into the `users` table.
     #[instrument(skip_all)]
-    pub async fn full(&self, page_start: usize, ignore_existed: bool) {
+    pub async fn users(&self) -> anyhow::Result<()> {
+        let mut url = Some(format!(
+            "{}/api/users?include=groups&sort=joinedAt",
+            self.config.base_url
+        ));
+        let mut total = 0;
+        while let Some(current) = url {
+            let page = get_users_page(current.as_str(), self.auth.as_deref()).await?;
+            User::save_all(&page.users, &self.conn).await;
+            total += page.users.len();
+            info!(total, "Saved users");
+            url = page.next;
+        }
+        Ok(())
+    }
+    /// Regenerates the Markdown and mentions of every post from its stored HTML without touching
+    /// the network. User mentions are resolved against the `users` table.
+    pub async fn reconvert(&self) {
+        let user_ids_by_slug = User::find_ids_by_username(&self.conn).await;
+        let (mut total, mut changed_total, mut after_id) = (0, 0, 0);
+        loop {
+            let mut posts = Post::find_with_html(after_id, POST_BATCH_SIZE, &self.conn).await;
+            let Some(last) = posts.last() else {
+                break;
+            };
+            after_id = last.id;
+            let mut changed = vec![];
+            for post in posts.iter_mut() {
+                let converted = convert_post_html(
+                    post.content_html.as_deref().unwrap_or_default(),
+                    &user_ids_by_slug,
+                );
+                let reply_to_id = converted
+                    .mentioned_post_ids
+                    .first()
+                    .copied()
+                    .unwrap_or_default();
+                post.mentioned_post_ids = converted.mentioned_post_ids;
+                post.mentioned_user_ids = converted.mentioned_user_ids;
+                if converted.content != post.content || reply_to_id != post.reply_to_id {
+                    post.content = converted.content;
+                    post.reply_to_id = reply_to_id;
+                    changed.push(post.clone());
+                }
+            }
+            Post::update_converted(&posts, &changed, &self.conn).await;
+            total += posts.len();
+            changed_total += changed.len();
+        }
+        info!(total, changed = changed_total, "Reconverted posts");
+    }
+    /// Collects the image and attachment URLs of every post and downloads the ones not mirrored
+    /// yet, with `retry_failed` also those that failed before.
+    #[instrument(skip_all)]
+    pub async fn assets(&self, retry_failed: bool) -> anyhow::Result<()> {
+        let config = self.config.assets.clone().context("no assets config")?;
+        let store = Arc::new(AssetStore::new(config, self.config.base_url.as_str()));
+        let mut after_id = 0;
+        loop {
+            let posts = Post::find_with_html(after_id, POST_BATCH_SIZE, &self.conn).await;
+            let Some(last) = posts.last() else {
+                break;
+            };
+            after_id = last.id;
+            let post_urls = posts
+                .into_iter()
+                .map(|post| {
+                    let html = post.content_html.unwrap_or_default();
+                    (
+                        post.id,
+                        extract_asset_urls(html.as_str(), self.config.base_url.as_str()),
+                    )
+                })
+                .collect_vec();
+            PostAsset::replace_all(&post_urls, &self.conn).await;
+        }
+        let urls = Asset::find_pending_urls(retry_failed, &self.conn).await;
+        info!(total = urls.len(), "Mirroring assets");
+        let semaphore = Arc::new(Semaphore::new(self.config.concurrency.max(1)));
+        let mut set = JoinSet::new();
+        for url in urls {
+            let (store, semaphore, conn) = (store.clone(), semaphore.clone(), self.conn.clone());
+            set.spawn(async move {
+                let _permit = semaphore.acquire_owned().await.unwrap();
+                let asset = store.mirror(url.as_str()).await;
+                asset.save(&conn).await;
+                asset.path.is_some()
+            });
+        }
+        let results = set.join_all().await;
+        let mirrored = results.iter().filter(|x| **x).count();
+        info!(
+            mirrored,
+            failed = results.len() - mirrored,
+            "Mirrored assets"
+        );
+        Ok(())
+    }
+    /// Re-crawls every stored discussion that is not yet marked as removed, so that deleted or
+    /// hidden discussions and posts, which no longer show up on index pages, get detected.
+    #[instrument(skip_all)]
+    pub async fn recheck(&self) {
+        let shutdown = Shutdown::listen();
+        let ids = Discussion::find_live_ids(&self.conn).await;
+        let (crawler, sender) =
+            Crawler::new(self.config.clone(), self.conn.clone(), self.auth.clone()).await;
+        let set = crawler.launch().await;
+        info!(total = ids.len(), "Rechecking discussions");
+        for id in ids {
+            if !enqueue(&sender, id, &shutdown).await {
+                break;
+            }
+        }
+        drop(sender);
+        set.join_all().await;
+    }
+    fn full_checkpoint_key(&self) -> String {
+        format!("full_checkpoint:{}", self.config.base_url)
+    }
+    /// Takes the ids the workers are done with off `pending_pages`, and saves the checkpoint at the
+    /// last page whose discussions, like those of every page before it, are all done.
+    async fn save_full_progress(
+        &self,
+        pending_pages: &mut VecDeque<(usize, HashSet<u64>)>,
+        done: &Receiver<u64>,
+    ) {
+        while let Ok(id) = done.try_recv() {
+            if let Some((_, ids)) = pending_pages.iter_mut().find(|(_, ids)| ids.contains(&id)) {
+                ids.remove(&id);
+            }
+        }
+        let mut completed_page = None;
+        while pending_pages.front().is_some_and(|(_, ids)| ids.is_empty()) {
+            completed_page = pending_pages.pop_front().map(|(page, _)| page);
+        }
+        let Some(page) = completed_page else {
+            return;
+        };
+        let checkpoint = FullCheckpoint {
+            offset: (page - 1) * 20,
+            sort: FULL_SORT.to_string(),
+        };
+        Kv::set(
+            self.full_checkpoint_key().as_str(),
+            serde_json::to_string(&checkpoint).unwrap().as_str(),
+            &self.conn,
+        )
+        .await;
+        info!(page, "Saved checkpoint");
+    }
+    /// Crawls every index page sorted by `createdAt`, starting at `page_start`, or with `resume`
+    /// at the page after the saved checkpoint, which is saved as soon as a page's discussions are
+    /// done. On SIGINT/SIGTERM, enqueueing stops and in-flight discussions are drained.
+    /// Fails once an index page still cannot be fetched after `throttle.max_retries` retries, or
+    /// when the retry budget runs out, after the discussions already enqueued are saved.
+    #[instrument(skip_all)]
+    pub async fn full(
+        &self,
+        page_start: usize,
+        ignore_existed: bool,
+        resume: bool,
+    ) -> anyhow::Result<()> {
+        let shutdown = Shutdown::listen();
+        if let Err(err) = self.tags().await {
+            warn!("Cannot refresh tags: {:#}", err);
+        }
+        let mut current_page = page_start;
+        if resume {
+            let checkpoint = Kv::get(self.full_checkpoint_key().as_str(), &self.conn)
+                .await
+                .and_then(|x| serde_json::from_str::<FullCheckpoint>(x.as_str()).ok());
+            match checkpoint {
+                Some(checkpoint) if checkpoint.sort == FULL_SORT => {
+                    current_page = checkpoint.offset / 20 + 2;
+                    info!(current_page, "Resuming from checkpoint");
+                }
+                _ => warn!(
+                    current_page,
+                    "No checkpoint found, starting from page_start"
+                ),
+            }
+        }
         let mut ignore_ids =
             Job::find_by_entity_status("discussion", JobStatus::Impossible, &self.conn)
                 .await
@@ -139,42 +657,74 @@ impl Cmd {
                     .map(|x| x.entity_id),
             );
         }
-        let (crawler, sender) = Crawler::new(self.config.clone(), self.conn.clone()).await;
+        let (mut crawler, sender) =
+            Crawler::new(self.config.clone(), self.conn.clone(), self.auth.clone()).await;
+        let done = crawler.report_done();
         let set = crawler.launch().await;
-        let mut current_page = page_start;
-        loop {
+        // Pages whose discussions were all enqueued, with the ids not done yet
+        let mut pending_pages = VecDeque::new();
+        let mut failure = None;
+        'pages: while !shutdown.is_requested() {
             info!(
                 current_page,
                 offset = (current_page - 1) * 20,
                 "Processing index page"
             );
+            let mut attempt = 0;
             let ids = loop {
                 match get_index_page(
                     self.config.base_url.as_str(),
                     current_page,
-                    Some("createdAt"),
+                    Some(FULL_SORT),
+                    self.auth.as_deref(),
                 )
                 .await
                 {
-                    Ok(res) => break res,
+                    Ok(res) => {
+                        User::save_all(&res.users, &self.conn).await;
+                        break res.entries.into_iter().map(|x| x.id).collect::<Vec<_>>();
+                    }
                     Err(err) => {
-                        error!("Error get index page: {:#}", err);
-                        sleep(Duration::from_secs(1)).await;
+                        let throttle = get_throttle();
+                        if attempt >= throttle.max_retries() || !throttle.withdraw_retry() {
+                            failure = Some(err.context(format!(
+                                "cannot get index page {current_page} after {attempt} retries"
+                            )));
+                            break 'pages;
+                        }
+                        let delay = throttle.backoff(attempt, None);
+                        error!(?delay, "Error get index page: {:#}", err);
+                        tokio::select! {
+                            _ = sleep(delay) => {}
+                            _ = shutdown.requested() => break 'pages,
+                        }
+                        attempt += 1;
                     }
                 }
             };
             if ids.is_empty() {
                 break;
             }
+            let mut enqueued = HashSet::new();
             for id in ids {
                 if ignore_ids.contains(&id) {
                     continue;
                 }
-                sender.send(id).await.unwrap();
+                if !enqueue(&sender, id, &shutdown).await {
+                    break 'pages;
+                }
+                enqueued.insert(id);
             }
+            pending_pages.push_back((current_page, enqueued));
+            self.save_full_progress(&mut pending_pages, &done).await;
             current_page += 1;
         }
         drop(sender);
         set.join_all().await;
+        self.save_full_progress(&mut pending_pages, &done).await;
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
     }
 }
@@ -7,6 +7,160 @@ pub struct Config {
     pub base_url: String,
     pub concurrency: usize,
     pub db: String,
+    #[serde(default = "default_auto_migrate")]
+    pub auto_migrate: bool,
+    #[serde(default)]
+    pub refetch_posts: bool, // re-download every stored post, to capture edits past the first page
+    pub auth: Option<AuthConfig>,
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    pub embedding: Option<EmbeddingConfig>,
+    pub assets: Option<AssetsConfig>,
+    #[serde(default)]
+    pub export: ExportConfig,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AuthConfig {
+    ApiKey { api_key: String, user_id: u64 },
+    Password { username: String, password: String },
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThrottleConfig {
+    #[serde(default)]
+    pub requests_per_second: f64, // per host, 0 for unlimited
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32, // per request, for 429, 5xx and network errors
+    #[serde(default = "default_backoff_base_ms")]
+    pub backoff_base_ms: u64,
+    #[serde(default = "default_backoff_max_ms")]
+    pub backoff_max_ms: u64,
+    #[serde(default = "default_retry_budget")]
+    pub retry_budget: f64, // retries shared by all requests
+    #[serde(default = "default_retry_budget_ratio")]
+    pub retry_budget_ratio: f64, // retries earned back per successful request
+}
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 0.0,
+            max_retries: default_max_retries(),
+            backoff_base_ms: default_backoff_base_ms(),
+            backoff_max_ms: default_backoff_max_ms(),
+            retry_budget: default_retry_budget(),
+            retry_budget_ratio: default_retry_budget_ratio(),
+        }
+    }
+}
+fn default_max_retries() -> u32 {
+    5
+}
+fn default_backoff_base_ms() -> u64 {
+    500
+}
+fn default_backoff_max_ms() -> u64 {
+    60_000
+}
+fn default_retry_budget() -> f64 {
+    100.0
+}
+fn default_retry_budget_ratio() -> f64 {
+    0.1
+}
+/// Limits for the `retry` subcommand.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    #[serde(default = "default_retry_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_retry_backoff_base_secs")]
+    pub backoff_base_secs: u64,
+    #[serde(default = "default_retry_backoff_max_secs")]
+    pub backoff_max_secs: u64,
+}
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_retry_max_attempts(),
+            backoff_base_secs: default_retry_backoff_base_secs(),
+            backoff_max_secs: default_retry_backoff_max_secs(),
+        }
+    }
+}
+impl RetryConfig {
+    /// How long a job stays ineligible for `retry` after its `attempts`-th failed attempt.
+    pub fn backoff(&self, attempts: u32) -> chrono::Duration {
+        let secs = self
+            .backoff_base_secs
+            .saturating_mul(2u64.saturating_pow(attempts.saturating_sub(1)))
+            .min(self.backoff_max_secs);
+        chrono::Duration::seconds(secs as i64)
+    }
+}
+fn default_retry_max_attempts() -> u32 {
+    5
+}
+fn default_retry_backoff_base_secs() -> u64 {
+    300
+}
+fn default_retry_backoff_max_secs() -> u64 {
+    86_400
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingConfig {
+    pub url: String, // e.g. http://localhost:8080/v1/embeddings
+    pub model: String,
+    pub api_key: Option<String>,
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize, // in characters
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+fn default_chunk_size() -> usize {
+    1000
+}
+fn default_batch_size() -> usize {
+    32
+}
+/// Where and what the `assets` subcommand mirrors.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetsConfig {
+    #[serde(default = "default_assets_dir")]
+    pub dir: String,
+    #[serde(default = "default_assets_max_size")]
+    pub max_size: u64, // in bytes, larger files are skipped
+    #[serde(default)]
+    pub allowed_domains: Vec<String>, // also matches subdomains, empty for the forum's host only
+    #[serde(default)]
+    pub rewrite_export_links: bool, // point Markdown exports at the local copies
+}
+fn default_assets_dir() -> String {
+    "assets".to_string()
+}
+fn default_assets_max_size() -> u64 {
+    20 * 1024 * 1024
+}
+/// Layout of the Markdown export.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportConfig {
+    pub template: Option<String>, // path to a minijinja template, the built-in layout when unset
+    #[serde(default = "default_export_extension")]
+    pub extension: String, // of the exported files
+}
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            template: None,
+            extension: default_export_extension(),
+        }
+    }
+}
+fn default_export_extension() -> String {
+    "md".to_string()
+}
+fn default_auto_migrate() -> bool {
+    true
 }
 impl Config {
     pub async fn load(path: &str) -> anyhow::Result<Self> {
@@ -1,16 +1,30 @@
 use crate::api::{
     GetDiscussionOptions, GetDiscussionOptionsBuilder, GetDiscussionResult, get_discussion,
 };
+use crate::auth::Auth;
 use crate::config::Config;
 use crate::entity::{Discussion, Job, JobStatus};
+use crate::shutdown::Shutdown;
 use async_channel::{Receiver, Sender};
+use chrono::Utc;
+use reqwest::StatusCode;
 use sqlx::SqlitePool;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Semaphore;
 use tokio::task::JoinSet;
 use tracing::{error, info, instrument, warn};
 
+/// Sends `id` to the workers, returning `false` if a shutdown was requested first.
+pub async fn enqueue(sender: &Sender<u64>, id: u64, shutdown: &Shutdown) -> bool {
+    if shutdown.is_requested() {
+        return false;
+    }
+    tokio::select! {
+        res = sender.send(id) => res.is_ok(),
+        _ = shutdown.requested() => false,
+    }
+}
 #[derive(Clone)]
 pub struct Crawler {
     config: Config,
@@ -18,12 +32,18 @@ pub struct Crawler {
     get_discussion_options: GetDiscussionOptions,
     sem: Arc<Semaphore>,
     conn: SqlitePool,
+    done: Option<Sender<u64>>, // see `report_done`
 }
 impl Crawler {
-    pub async fn new(config: Config, conn: SqlitePool) -> (Self, Sender<u64>) {
+    pub async fn new(
+        config: Config,
+        conn: SqlitePool,
+        auth: Option<Arc<Auth>>,
+    ) -> (Self, Sender<u64>) {
         let get_discussion_options = GetDiscussionOptionsBuilder::default()
             .base_url(config.base_url.to_string())
             .concurrency(config.concurrency)
+            .auth(auth)
             .build()
             .unwrap();
         let (sender, receiver) = async_channel::bounded::<u64>(1);
@@ -34,10 +54,18 @@ impl Crawler {
                 receiver,
                 get_discussion_options,
                 conn,
+                done: None,
             },
             sender,
         )
     }
+    /// Has the workers send each discussion id to the returned receiver once they are done with it,
+    /// saved or not. Call before `launch`.
+    pub fn report_done(&mut self) -> Receiver<u64> {
+        let (sender, receiver) = async_channel::unbounded();
+        self.done = Some(sender);
+        receiver
+    }
     pub async fn launch(&self) -> JoinSet<()> {
         let mut set = JoinSet::new();
         for i in 1..=self.config.concurrency {
@@ -51,60 +79,77 @@ impl Crawler {
         while let Ok(id) = self.receiver.recv().await {
             info!(id, "Getting discussion");
             let mut options = self.get_discussion_options.clone();
-            if let Some(discussion) = Discussion::find_by_id(id, &self.conn).await {
-                options.existing_post_ids = discussion
+            if !self.config.refetch_posts
+                && let Some(discussion) = Discussion::find_by_id(id, &self.conn).await
+            {
+                // Posts marked as removed are fetched again in case they came back
+                options.existing_posts = discussion
                     .posts
                     .into_iter()
-                    .map(|x| x.id)
-                    .collect::<HashSet<_>>();
+                    .filter(|x| !x.is_removed())
+                    .map(|x| (x.id, x.edited_at))
+                    .collect::<HashMap<_, _>>();
             }
             let get_discussion_res = get_discussion(id, options, Some(self.sem.clone())).await;
             match get_discussion_res {
                 Ok(discussion_res) => match discussion_res {
-                    GetDiscussionResult::Impossible => {
-                        warn!(id, "Impossible to get discussion");
-                        Job {
-                            entity: "discussion".to_string(),
-                            entity_id: id,
-                            status: JobStatus::Impossible,
+                    GetDiscussionResult::Impossible(status) => {
+                        warn!(id, %status, "Impossible to get discussion");
+                        // without auth, a 403 may only mean that a login is needed
+                        if status == StatusCode::NOT_FOUND {
+                            Discussion::mark_removed(id, false, &self.conn).await;
+                        } else if self.get_discussion_options.auth.is_some() {
+                            Discussion::mark_removed(id, true, &self.conn).await;
                         }
-                        .save(&self.conn)
-                        .await;
+                        self.save_job(id, JobStatus::Impossible, Some(status.to_string()))
+                            .await;
                     }
                     GetDiscussionResult::Ok(discussion) => {
                         discussion.save_with_posts(&self.conn).await;
-                        Job {
-                            entity: "discussion".to_string(),
-                            entity_id: id,
-                            status: JobStatus::Success,
-                        }
-                        .save(&self.conn)
-                        .await;
+                        self.save_job(id, JobStatus::Success, None).await;
                         info!(id, "Saved discussion");
                     }
-                    GetDiscussionResult::PartialError(discussion) => {
+                    GetDiscussionResult::PartialError(discussion, err) => {
                         discussion.save_with_posts(&self.conn).await;
-                        Job {
-                            entity: "discussion".to_string(),
-                            entity_id: id,
-                            status: JobStatus::Partial,
-                        }
-                        .save(&self.conn)
-                        .await;
-                        warn!(id, "Saved discussion (partial)");
+                        warn!(id, "Saved discussion (partial): {}", err);
+                        self.save_job(id, JobStatus::Partial, Some(err)).await;
                     }
                 },
                 Err(err) => {
                     error!(id, "Cannot get discussion: {:#}", err);
-                    Job {
-                        entity: "discussion".to_string(),
-                        entity_id: id,
-                        status: JobStatus::Failed,
-                    }
-                    .save(&self.conn)
-                    .await;
+                    self.save_job(id, JobStatus::Failed, Some(format!("{err:#}")))
+                        .await;
                 }
             }
+            if let Some(done) = &self.done {
+                let _ = done.try_send(id);
+            }
+        }
+    }
+    /// Records an attempt, scheduling failed and partial jobs for `retry` after a backoff.
+    async fn save_job(&self, id: u64, status: JobStatus, last_error: Option<String>) {
+        let now = Utc::now().fixed_offset();
+        let previous = Job::find("discussion", id, &self.conn).await;
+        let attempts = match &previous {
+            Some(job) if !job.status.is_finished() => job.attempts + 1,
+            _ => 1,
+        };
+        let next_eligible_at = if status.is_finished() {
+            None
+        } else {
+            Some(now + self.config.retry.backoff(attempts))
+        };
+        Job {
+            entity: "discussion".to_string(),
+            entity_id: id,
+            status,
+            attempts,
+            first_seen_at: previous.and_then(|x| x.first_seen_at).or(Some(now)),
+            last_attempt_at: Some(now),
+            next_eligible_at,
+            last_error,
         }
+        .save(&self.conn)
+        .await;
     }
 }
@@ -1,5 +1,21 @@
 use sqlx::SqlitePool;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+use std::str::FromStr;
+use tracing::info;
+
+static MIGRATOR: Migrator = sqlx::migrate!();
 
 pub async fn get_connection_pool(path: &str) -> anyhow::Result<SqlitePool> {
-    Ok(SqlitePool::connect(path).await?)
+    // WAL lets the crawler workers write concurrently without deadlocking on upgraded locks
+    let options = SqliteConnectOptions::from_str(path)?
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal);
+    Ok(SqlitePool::connect_with(options).await?)
+}
+pub async fn migrate(pool: &SqlitePool) -> anyhow::Result<i64> {
+    MIGRATOR.run(pool).await?;
+    let version = MIGRATOR.iter().map(|x| x.version).max().unwrap_or_default();
+    info!(version, "Database schema is up to date");
+    Ok(version)
 }
@@ -0,0 +1,131 @@
+use crate::config::EmbeddingConfig;
+use anyhow::bail;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tracing::debug;
+
+#[derive(Debug, Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EmbeddingClient {
+    config: EmbeddingConfig,
+    client: Client,
+}
+impl EmbeddingClient {
+    pub fn new(config: EmbeddingConfig) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(120))
+            .build()
+            .unwrap();
+        Self { config, client }
+    }
+    pub fn model(&self) -> &str {
+        self.config.model.as_str()
+    }
+    /// Calls the OpenAI-compatible embeddings endpoint, splitting `inputs` into requests of at
+    /// most `batch_size` items. The returned vectors are in the same order as `inputs`.
+    pub async fn embed(&self, inputs: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let mut result = Vec::with_capacity(inputs.len());
+        for batch in inputs.chunks(self.config.batch_size.max(1)) {
+            debug!(len = batch.len(), "Requesting embeddings");
+            let mut request = self
+                .client
+                .post(self.config.url.as_str())
+                .json(&EmbeddingRequest {
+                    model: self.config.model.as_str(),
+                    input: batch,
+                });
+            if let Some(api_key) = &self.config.api_key {
+                request = request.bearer_auth(api_key);
+            }
+            let response = match request.send().await?.error_for_status() {
+                Ok(response) => response,
+                Err(err) => {
+                    bail!("response error status: {}", err);
+                }
+            };
+            let mut payload: EmbeddingResponse = response.json().await?;
+            if payload.data.len() != batch.len() {
+                bail!(
+                    "expected {} embeddings, got {}",
+                    batch.len(),
+                    payload.data.len()
+                );
+            }
+            payload.data.sort_by_key(|x| x.index);
+            result.extend(payload.data.into_iter().map(|x| x.embedding));
+        }
+        Ok(result)
+    }
+}
+
+/// Splits `text` into chunks of at most `chunk_size` characters, preferring paragraph boundaries.
+pub fn chunk_text(text: &str, chunk_size: usize) -> Vec<String> {
+    let chunk_size = chunk_size.max(1);
+    let mut chunks = vec![];
+    let mut current = String::new();
+    let mut current_len = 0;
+    for paragraph in text
+        .split("\n\n")
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+    {
+        let len = paragraph.chars().count();
+        if current_len > 0 && current_len + 2 + len > chunk_size {
+            chunks.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        if len > chunk_size {
+            let chars = paragraph.chars().collect::<Vec<_>>();
+            for part in chars.chunks(chunk_size) {
+                chunks.push(part.iter().collect());
+            }
+            continue;
+        }
+        if current_len > 0 {
+            current.push_str("\n\n");
+            current_len += 2;
+        }
+        current.push_str(paragraph);
+        current_len += len;
+    }
+    if current_len > 0 {
+        chunks.push(current);
+    }
+    chunks
+}
+
+pub fn content_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let (mut dot, mut norm_a, mut norm_b) = (0f32, 0f32, 0f32);
+    for (x, y) in a.iter().zip(b.iter()) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
@@ -1,11 +1,22 @@
-use anyhow::anyhow;
+use crate::embedding::cosine_similarity;
+use anyhow::{anyhow, bail};
 use chrono::{FixedOffset, Utc};
+use futures::TryStreamExt;
+use futures::stream::BoxStream;
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
-use sqlx::{Error, Executor, FromRow, QueryBuilder, Sqlite, SqlitePool, query, query_as};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::query::QueryAs;
+use sqlx::sqlite::SqliteArguments;
+use sqlx::{
+    FromRow, QueryBuilder, Sqlite, SqliteConnection, SqlitePool, query, query_as, query_scalar,
+};
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Display;
+use std::sync::LazyLock;
+
+/// SQLite's limit on the variables of one statement; longer inserts are split into chunks.
+const MAX_VARIABLES: usize = 32766;
 
 #[derive(Debug, Clone, Default, FromRow, Serialize)]
 pub struct Post {
@@ -17,8 +28,96 @@ pub struct Post {
     pub user_display_name: String,
     pub content: String,
     pub created_at: chrono::DateTime<FixedOffset>,
+    pub edited_at: Option<chrono::DateTime<FixedOffset>>,
+    pub edited_user_id: u64,
+    pub deleted_at: Option<chrono::DateTime<FixedOffset>>,
+    pub hidden_at: Option<chrono::DateTime<FixedOffset>>,
+    pub removal_detected_at: Option<chrono::DateTime<FixedOffset>>,
+    pub number: u64,
+    pub event_type: Option<String>, // None for comments
+    #[sqlx(json(nullable))]
+    pub event_payload: Option<serde_json::Value>,
+    pub content_html: Option<String>, // what content is converted from, None for event posts
+    pub source: Option<String>,       // only exposed to users who may edit the post
+    #[sqlx(skip)]
+    pub mentioned_post_ids: Vec<u64>, // reply_to_id is the first of them
+    #[sqlx(skip)]
+    pub mentioned_user_ids: Vec<u64>,
+    #[sqlx(skip)]
+    pub reply_ids: Vec<u64>, // posts of the same discussion mentioning this one, see `link_replies`
 }
 impl Post {
+    /// Link to post `number` on the forum, or to its discussion while the number is unknown (0).
+    pub fn url_of(base_url: &str, discussion_id: u64, number: u64) -> String {
+        match number {
+            0 => format!("{base_url}/d/{discussion_id}"),
+            number => format!("{base_url}/d/{discussion_id}/{number}"),
+        }
+    }
+    pub fn is_removed(&self) -> bool {
+        self.deleted_at.is_some() || self.hidden_at.is_some()
+    }
+    pub fn is_event(&self) -> bool {
+        self.event_type.is_some()
+    }
+    /// Describes an event post, e.g. `renamed the discussion from "a" to "b"`.
+    pub fn event_description(&self) -> Option<String> {
+        let event_type = self.event_type.as_deref()?;
+        let payload = self.event_payload.clone().unwrap_or_default();
+        let toggled = |key: &str, on: &str, off: &str| {
+            if payload[key].as_bool().unwrap_or_default() {
+                on.to_string()
+            } else {
+                off.to_string()
+            }
+        };
+        Some(match event_type {
+            "discussionRenamed" => format!(
+                "renamed the discussion from {} to {}",
+                payload[0], payload[1]
+            ),
+            "discussionTagged" => {
+                format!("changed the tag ids from {} to {}", payload[0], payload[1])
+            }
+            "discussionStickied" => toggled(
+                "sticky",
+                "stickied the discussion",
+                "unstickied the discussion",
+            ),
+            "discussionLocked" => {
+                toggled("locked", "locked the discussion", "unlocked the discussion")
+            }
+            _ => format!("{event_type}: {payload}"),
+        })
+    }
+    /// The posts of the discussions matching `filter`, ordered by discussion and id, read as the
+    /// stream is consumed.
+    pub fn stream_by_filter<'a>(
+        filter: &ExportFilter,
+        pool: &'a SqlitePool,
+    ) -> BoxStream<'a, Result<Post, sqlx::Error>> {
+        filter
+            .bind_to(query_as(EXPORT_POSTS_SQL.as_str()))
+            .fetch(pool)
+    }
+    /// Fills the mentions and `reply_ids` of the posts of a discussion.
+    pub async fn load_mentions(discussion_id: u64, posts: &mut [Post], pool: &SqlitePool) {
+        let mentions = PostMention::find_by_discussion_id(discussion_id, pool).await;
+        for post in posts.iter_mut() {
+            PostMention::attach(&mentions, post);
+        }
+        Post::link_replies(posts);
+    }
+    /// Fills `reply_ids` from the `mentioned_post_ids` of the other posts in `posts`.
+    pub fn link_replies(posts: &mut [Post]) {
+        let replies = posts
+            .iter()
+            .flat_map(|x| x.mentioned_post_ids.iter().map(|parent| (*parent, x.id)))
+            .into_group_map();
+        for post in posts.iter_mut() {
+            post.reply_ids = replies.get(&post.id).cloned().unwrap_or_default();
+        }
+    }
     pub async fn find_by_discussion_id(id: u64, pool: &SqlitePool) -> Vec<Post> {
         query_as(r"select * from posts where discussion_id=?")
             .bind(id as i64)
@@ -26,32 +125,234 @@ impl Post {
             .await
             .unwrap()
     }
+    /// The next `limit` posts with stored HTML after `after_id`, for walking every post in batches.
+    pub async fn find_with_html(after_id: u64, limit: usize, pool: &SqlitePool) -> Vec<Post> {
+        query_as(
+            r"select * from posts where content_html is not null and id > ? order by id limit ?",
+        )
+        .bind(after_id as i64)
+        .bind(limit as i64)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+    /// Writes back content regenerated from the stored HTML. Mentions are replaced for all of
+    /// `posts`, content only for those in `changed`.
+    pub async fn update_converted(posts: &[Post], changed: &[Post], pool: &SqlitePool) {
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.unwrap();
+        for chunk in posts.chunks(1000) {
+            PostMention::replace(chunk, &mut tx).await;
+        }
+        for post in changed {
+            query(r"update posts set content=?, reply_to_id=? where id=?")
+                .bind(&post.content)
+                .bind(post.reply_to_id as i64)
+                .bind(post.id as i64)
+                .execute(&mut *tx)
+                .await
+                .unwrap();
+        }
+        tx.commit().await.unwrap();
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct PostMention {
+    pub post_id: u64,
+    pub kind: String, // "post" or "user"
+    pub mentioned_id: u64,
+}
+impl PostMention {
+    pub async fn find_by_discussion_id(id: u64, pool: &SqlitePool) -> Vec<PostMention> {
+        query_as(
+            r"select m.* from post_mentions m join posts p on p.id=m.post_id where p.discussion_id=? order by m.rowid",
+        )
+        .bind(id as i64)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+    /// Fills `mentioned_post_ids` and `mentioned_user_ids` of `post`.
+    pub fn attach(mentions: &[PostMention], post: &mut Post) {
+        for mention in mentions.iter().filter(|x| x.post_id == post.id) {
+            match mention.kind.as_str() {
+                "post" => post.mentioned_post_ids.push(mention.mentioned_id),
+                _ => post.mentioned_user_ids.push(mention.mentioned_id),
+            }
+        }
+    }
+    async fn replace(posts: &[Post], conn: &mut SqliteConnection) {
+        for chunk in posts.chunks(MAX_VARIABLES) {
+            let mut query_builder: QueryBuilder<Sqlite> =
+                QueryBuilder::new("delete from post_mentions where post_id in (");
+            let mut separated = query_builder.separated(", ");
+            for post in chunk {
+                separated.push_bind(post.id as i64);
+            }
+            separated.push_unseparated(")");
+            query_builder.build().execute(&mut *conn).await.unwrap();
+        }
+        let mentions = posts
+            .iter()
+            .flat_map(|post| {
+                let posts = post
+                    .mentioned_post_ids
+                    .iter()
+                    .map(|x| (post.id, "post", *x));
+                let users = post
+                    .mentioned_user_ids
+                    .iter()
+                    .map(|x| (post.id, "user", *x));
+                posts.chain(users)
+            })
+            .collect_vec();
+        // 3 variables per mention
+        for chunk in mentions.chunks(MAX_VARIABLES / 3) {
+            let mut query_builder: QueryBuilder<Sqlite> =
+                QueryBuilder::new("INSERT INTO post_mentions (post_id, kind, mentioned_id) ");
+            query_builder.push_values(chunk, |mut b, (post_id, kind, mentioned_id)| {
+                b.push_bind(*post_id as i64)
+                    .push_bind(*kind)
+                    .push_bind(*mentioned_id as i64);
+            });
+            query_builder.push(" ON CONFLICT DO NOTHING");
+            query_builder.build().execute(&mut *conn).await.unwrap();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, FromRow, Serialize)]
+pub struct Asset {
+    pub url: String,
+    pub sha256: Option<String>,
+    pub path: Option<String>, // relative to the assets dir, None when not mirrored
+    pub content_type: Option<String>,
+    pub size: Option<u64>,
+    pub error: Option<String>,
+    pub fetched_at: chrono::DateTime<FixedOffset>,
+}
+impl Asset {
+    /// Returns url -> path of every mirrored asset referenced by the posts of a discussion.
+    pub async fn find_local_paths_by_discussion_id(
+        id: u64,
+        pool: &SqlitePool,
+    ) -> HashMap<String, String> {
+        query_as::<_, (String, String)>(
+            r"select distinct a.url, a.path from assets a join post_assets pa on pa.url=a.url
+            join posts p on p.id=pa.post_id where p.discussion_id=? and a.path is not null",
+        )
+        .bind(id as i64)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .collect()
+    }
+    /// URLs referenced by posts that have not been downloaded yet, or with `retry_failed` also
+    /// those whose download failed.
+    pub async fn find_pending_urls(retry_failed: bool, pool: &SqlitePool) -> Vec<String> {
+        query_scalar(
+            r"select distinct pa.url from post_assets pa left join assets a on a.url=pa.url
+            where a.url is null or (? and a.path is null) order by pa.url",
+        )
+        .bind(retry_failed)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
     pub async fn save(&self, pool: &SqlitePool) {
         query(
             r#"
-            INSERT INTO posts (id, user_id, discussion_id, reply_to_id, username, user_display_name, content, created_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
-            ON CONFLICT (id) DO UPDATE SET
-                user_id = EXCLUDED.user_id,
-                discussion_id = EXCLUDED.discussion_id,
-                reply_to_id = EXCLUDED.reply_to_id,
-                username = EXCLUDED.username,
-                user_display_name = EXCLUDED.user_display_name,
-                content = EXCLUDED.content,
-                created_at = EXCLUDED.created_at
+            INSERT INTO assets (url, sha256, path, content_type, size, error, fetched_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (url) DO UPDATE SET
+                sha256 = EXCLUDED.sha256,
+                path = EXCLUDED.path,
+                content_type = EXCLUDED.content_type,
+                size = EXCLUDED.size,
+                error = EXCLUDED.error,
+                fetched_at = EXCLUDED.fetched_at
             "#,
         )
-            .bind(self.id as i64)
-            .bind(self.user_id as i64)
-            .bind(self.discussion_id as i64)
-            .bind(self.reply_to_id as i64)
-            .bind(&self.username)
-            .bind(&self.user_display_name)
-            .bind(&self.content)
-            .bind(self.created_at)
-            .execute(pool)
+        .bind(&self.url)
+        .bind(&self.sha256)
+        .bind(&self.path)
+        .bind(&self.content_type)
+        .bind(self.size.map(|x| x as i64))
+        .bind(&self.error)
+        .bind(self.fetched_at)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+}
+pub struct PostAsset;
+impl PostAsset {
+    /// Replaces the asset URLs of each post in `post_urls`.
+    pub async fn replace_all(post_urls: &[(u64, Vec<String>)], pool: &SqlitePool) {
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.unwrap();
+        for chunk in post_urls.chunks(1000) {
+            let mut query_builder: QueryBuilder<Sqlite> =
+                QueryBuilder::new("delete from post_assets where post_id in (");
+            let mut separated = query_builder.separated(", ");
+            for (post_id, _) in chunk {
+                separated.push_bind(*post_id as i64);
+            }
+            separated.push_unseparated(")");
+            query_builder.build().execute(&mut *tx).await.unwrap();
+            let rows = chunk
+                .iter()
+                .flat_map(|(post_id, urls)| urls.iter().map(|x| (*post_id, x)))
+                .collect_vec();
+            if rows.is_empty() {
+                continue;
+            }
+            let mut query_builder: QueryBuilder<Sqlite> =
+                QueryBuilder::new("INSERT INTO post_assets (post_id, url) ");
+            query_builder.push_values(&rows, |mut b, (post_id, url)| {
+                b.push_bind(*post_id as i64).push_bind(*url);
+            });
+            query_builder.push(" ON CONFLICT DO NOTHING");
+            query_builder.build().execute(&mut *tx).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+    }
+}
+
+/// A previous version of a post, recorded by a trigger when a re-crawl overwrites its content.
+#[derive(Debug, Clone, Default, FromRow, Serialize)]
+pub struct PostRevision {
+    pub id: u64,
+    pub post_id: u64,
+    pub content: String,
+    pub content_html: Option<String>,
+    pub source: Option<String>,
+    pub edited_at: Option<chrono::DateTime<FixedOffset>>,
+    pub edited_user_id: u64,
+    pub replaced_at: chrono::DateTime<FixedOffset>,
+}
+impl PostRevision {
+    pub async fn find_by_post_id(post_id: u64, pool: &SqlitePool) -> Vec<PostRevision> {
+        query_as(r"select * from post_revisions where post_id=? order by id")
+            .bind(post_id as i64)
+            .fetch_all(pool)
             .await
-            .unwrap();
+            .unwrap()
+    }
+    /// Returns post id -> revisions (oldest first) for every edited post of a discussion.
+    pub async fn find_grouped_by_discussion_id(
+        id: u64,
+        pool: &SqlitePool,
+    ) -> HashMap<u64, Vec<PostRevision>> {
+        query_as::<_, PostRevision>(
+            r"select r.* from post_revisions r join posts p on p.id=r.post_id where p.discussion_id=? order by r.id",
+        )
+        .bind(id as i64)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .into_group_map_by(|x| x.post_id)
     }
 }
 
@@ -65,56 +366,370 @@ pub struct Discussion {
     #[sqlx(json)]
     pub tags: Vec<String>,
     #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub posts: Vec<Post>,
     pub is_frontpage: bool,
     pub created_at: chrono::DateTime<FixedOffset>,
+    pub deleted_at: Option<chrono::DateTime<FixedOffset>>,
+    pub hidden_at: Option<chrono::DateTime<FixedOffset>>,
+    pub removal_detected_at: Option<chrono::DateTime<FixedOffset>>,
+    pub slug: String,
+    pub comment_count: u64,
+    pub participant_count: u64,
+    pub last_post_number: u64,
+    pub is_sticky: bool,
+    pub is_locked: bool,
+    pub is_approved: bool,
+    pub last_posted_at: Option<chrono::DateTime<FixedOffset>>,
+    /// Every post id currently listed by the forum, used to detect removed posts on save.
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub post_ids: Vec<u64>,
+    /// Users included in the responses, saved along with the discussion.
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub users: Vec<User>,
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub tag_ids: Vec<u64>,
+    /// Tags included in the response. Their parent is not always known, so it is left unchanged.
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub included_tags: Vec<Tag>,
 }
-#[derive(Debug, Clone, Default, FromRow, Serialize)]
-pub struct DiscussionExtended {
-    #[sqlx(flatten)]
-    #[serde(flatten)]
-    pub discussion: Discussion,
-    pub last_posted_at: chrono::DateTime<FixedOffset>,
+/// Which discussions `export` writes.
+#[derive(Debug, Clone, Default)]
+pub struct ExportFilter {
+    pub include_removed: bool,
+    pub tag: Option<String>,
+    pub user_id: Option<u64>, // of the discussion's author
+    pub is_frontpage: Option<bool>,
+    pub created_after: Option<chrono::DateTime<FixedOffset>>,
+    pub created_before: Option<chrono::DateTime<FixedOffset>>,
+    pub min_id: Option<u64>,
+    pub max_id: Option<u64>,
+    pub changed_since: Option<String>, // compared with `discussions.changed_at`
+}
+// The queries are static so that the streams do not borrow a QueryBuilder; unset filters bind NULL.
+const EXPORT_FILTER_CONDITIONS: &str = r"(?1 or (d.deleted_at is null and d.hidden_at is null))
+    and (?2 is null or exists (select 1 from discussion_tags dt join tags t on t.id = dt.tag_id
+        where dt.discussion_id = d.id and (t.name = ?2 or t.slug = ?2)))
+    and (?3 is null or d.user_id = ?3)
+    and (?4 is null or d.is_frontpage = ?4)
+    and (?5 is null or julianday(d.created_at) >= julianday(?5))
+    and (?6 is null or julianday(d.created_at) < julianday(?6))
+    and (?7 is null or d.id >= ?7)
+    and (?8 is null or d.id <= ?8)
+    and (?9 is null or d.changed_at >= ?9)";
+static EXPORT_DISCUSSIONS_SQL: LazyLock<String> = LazyLock::new(|| {
+    format!("select d.* from discussions d where {EXPORT_FILTER_CONDITIONS} order by d.id")
+});
+static EXPORT_POSTS_SQL: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        "select p.* from posts p where (?1 or (p.deleted_at is null and p.hidden_at is null)) \
+        and p.discussion_id in (select d.id from discussions d where {EXPORT_FILTER_CONDITIONS}) \
+        order by p.discussion_id, p.id"
+    )
+});
+static EXPORT_REMOVED_IDS_SQL: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        "select d.id from discussions d where {EXPORT_FILTER_CONDITIONS} \
+        and (d.deleted_at is not null or d.hidden_at is not null) order by d.id"
+    )
+});
+impl ExportFilter {
+    fn bind_to<'q, O>(
+        &self,
+        query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>,
+    ) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+        query
+            .bind(self.include_removed)
+            .bind(self.tag.clone())
+            .bind(self.user_id.map(|x| x as i64))
+            .bind(self.is_frontpage)
+            .bind(self.created_after)
+            .bind(self.created_before)
+            .bind(self.min_id.map(|x| x as i64))
+            .bind(self.max_id.map(|x| x as i64))
+            .bind(self.changed_since.clone())
+    }
+}
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscussionSort {
+    #[default]
+    CreatedAt,
+    LastPostedAt,
+    CommentCount,
+}
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DiscussionFilter {
+    pub tag: Option<String>,
+    pub tag_id: Option<u64>, // also matches its child tags
+    pub user_id: Option<u64>,
+    pub is_frontpage: Option<bool>,
+    pub is_sticky: Option<bool>,
+    pub is_locked: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_query_datetime")]
+    pub created_after: Option<chrono::DateTime<FixedOffset>>,
+    #[serde(default, deserialize_with = "deserialize_query_datetime")]
+    pub created_before: Option<chrono::DateTime<FixedOffset>>,
+    #[serde(default)]
+    pub sort: DiscussionSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    #[serde(default)]
+    pub include_removed: bool,
+}
+fn deserialize_query_datetime<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<chrono::DateTime<FixedOffset>>, D::Error> {
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    parse_query_datetime(value.as_str())
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+/// Parses an RFC 3339 time from a query string, where the `+` of an offset that was not written as
+/// `%2B` arrives decoded as a space.
+fn parse_query_datetime(value: &str) -> Result<chrono::DateTime<FixedOffset>, chrono::ParseError> {
+    chrono::DateTime::parse_from_rfc3339(value).or_else(|err| match value.rsplit_once(' ') {
+        Some((time, offset)) => {
+            chrono::DateTime::parse_from_rfc3339(format!("{time}+{offset}").as_str())
+                .map_err(|_| err)
+        }
+        None => Err(err),
+    })
+}
+/// What is stored about a discussion's activity, for comparing with the index page.
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct DiscussionActivity {
+    pub id: u64,
+    pub last_posted_at: Option<chrono::DateTime<FixedOffset>>,
+    pub comment_count: u64,
 }
 #[derive(Debug, Clone, Default)]
 pub struct DiscussionWithPosts {
     pub discussion: Discussion,
     pub posts: Vec<Post>,
 }
+/// Reads discussions with their posts one at a time, merging a discussion and a post cursor that
+/// are both ordered by discussion id, so memory use does not grow with the archive. Discussions
+/// without posts are skipped.
+pub struct DiscussionCursor<'a> {
+    discussions: BoxStream<'a, Result<Discussion, sqlx::Error>>,
+    posts: BoxStream<'a, Result<Post, sqlx::Error>>,
+    pending: Option<Post>, // the first post of the next discussion
+    pub skipped: usize,    // discussions without posts
+}
+impl<'a> DiscussionCursor<'a> {
+    pub fn new(filter: &ExportFilter, pool: &'a SqlitePool) -> Self {
+        Self {
+            discussions: Discussion::stream_by_filter(filter, pool),
+            posts: Post::stream_by_filter(filter, pool),
+            pending: None,
+            skipped: 0,
+        }
+    }
+    pub async fn next(&mut self) -> Option<DiscussionWithPosts> {
+        while let Some(discussion) = self.discussions.try_next().await.unwrap() {
+            let mut posts = vec![];
+            loop {
+                let post = match self.pending.take() {
+                    Some(post) => post,
+                    None => match self.posts.try_next().await.unwrap() {
+                        Some(post) => post,
+                        None => break,
+                    },
+                };
+                if post.discussion_id > discussion.id {
+                    self.pending = Some(post);
+                    break;
+                }
+                // posts of a discussion that is removed or missing are skipped
+                if post.discussion_id == discussion.id {
+                    posts.push(post);
+                }
+            }
+            if posts.is_empty() {
+                self.skipped += 1;
+                continue;
+            }
+            return Some(DiscussionWithPosts { discussion, posts });
+        }
+        None
+    }
+}
 impl Discussion {
-    pub async fn find_all_discussions_with_posts(pool: &SqlitePool) -> Vec<DiscussionWithPosts> {
-        let discussions = query_as::<_, Discussion>("select * from discussions")
+    pub fn is_removed(&self) -> bool {
+        self.deleted_at.is_some() || self.hidden_at.is_some()
+    }
+    fn push_filter_conditions<'a>(
+        query_builder: &mut QueryBuilder<'a, Sqlite>,
+        filter: &'a DiscussionFilter,
+    ) {
+        query_builder.push(" where 1=1");
+        if !filter.include_removed {
+            query_builder.push(" and d.deleted_at is null and d.hidden_at is null");
+        }
+        if let Some(tag) = &filter.tag {
+            // by the current name in `tags`, since `d.tags` keeps the names as crawled
+            query_builder
+                .push(
+                    " and exists (select 1 from discussion_tags dt join tags t on t.id = dt.tag_id \
+                    where dt.discussion_id = d.id and (t.name = ",
+                )
+                .push_bind(tag)
+                .push(" or t.slug = ")
+                .push_bind(tag)
+                .push("))");
+        }
+        if let Some(tag_id) = filter.tag_id {
+            query_builder
+                .push(
+                    " and exists (select 1 from discussion_tags dt join tags t on t.id = dt.tag_id \
+                    where dt.discussion_id = d.id and (t.id = ",
+                )
+                .push_bind(tag_id as i64)
+                .push(" or t.parent_id = ")
+                .push_bind(tag_id as i64)
+                .push("))");
+        }
+        if let Some(user_id) = filter.user_id {
+            query_builder
+                .push(" and d.user_id = ")
+                .push_bind(user_id as i64);
+        }
+        if let Some(is_frontpage) = filter.is_frontpage {
+            query_builder
+                .push(" and d.is_frontpage = ")
+                .push_bind(is_frontpage);
+        }
+        if let Some(is_sticky) = filter.is_sticky {
+            query_builder
+                .push(" and d.is_sticky = ")
+                .push_bind(is_sticky);
+        }
+        if let Some(is_locked) = filter.is_locked {
+            query_builder
+                .push(" and d.is_locked = ")
+                .push_bind(is_locked);
+        }
+        // julianday compares instants, the stored text may carry a different offset than the bound one
+        if let Some(created_after) = filter.created_after {
+            query_builder
+                .push(" and julianday(d.created_at) >= julianday(")
+                .push_bind(created_after)
+                .push(")");
+        }
+        if let Some(created_before) = filter.created_before {
+            query_builder
+                .push(" and julianday(d.created_at) < julianday(")
+                .push_bind(created_before)
+                .push(")");
+        }
+    }
+    /// Lists discussions without their posts. Returns the page and the total count matching `filter`.
+    pub async fn find_extended_by_filter(
+        filter: &DiscussionFilter,
+        limit: u32,
+        offset: u32,
+        pool: &SqlitePool,
+    ) -> (Vec<Discussion>, u64) {
+        let mut count_builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("select count(*) from discussions d");
+        Self::push_filter_conditions(&mut count_builder, filter);
+        let total = count_builder
+            .build_query_scalar::<i64>()
+            .fetch_one(pool)
+            .await
+            .unwrap() as u64;
+        let mut query_builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("select d.* from discussions d");
+        Self::push_filter_conditions(&mut query_builder, filter);
+        query_builder.push(match filter.sort {
+            DiscussionSort::CreatedAt => " order by d.created_at",
+            DiscussionSort::LastPostedAt => " order by coalesce(d.last_posted_at, d.created_at)",
+            DiscussionSort::CommentCount => " order by d.comment_count",
+        });
+        query_builder.push(match filter.order {
+            SortOrder::Asc => " asc, d.id asc",
+            SortOrder::Desc => " desc, d.id desc",
+        });
+        query_builder
+            .push(" limit ")
+            .push_bind(limit)
+            .push(" offset ")
+            .push_bind(offset);
+        let discussions = query_builder
+            .build_query_as::<Discussion>()
             .fetch_all(pool)
             .await
             .unwrap();
-        let mut posts_map = query_as::<_, Post>("select * from posts")
+        (discussions, total)
+    }
+    pub async fn find_activity_by_ids(
+        ids: &[u64],
+        pool: &SqlitePool,
+    ) -> HashMap<u64, DiscussionActivity> {
+        if ids.is_empty() {
+            return HashMap::new();
+        }
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "select id, last_posted_at, comment_count from discussions where id in (",
+        );
+        let mut separated = query_builder.separated(", ");
+        for id in ids {
+            separated.push_bind(*id as i64);
+        }
+        separated.push_unseparated(")");
+        query_builder
+            .build_query_as::<DiscussionActivity>()
             .fetch_all(pool)
             .await
             .unwrap()
             .into_iter()
-            .into_group_map_by(|x| x.discussion_id);
-        let mut result = vec![];
-        for discussion in discussions.into_iter() {
-            let Some(posts) = posts_map.get_mut(&discussion.id) else {
-                continue;
-            };
-            posts.sort_by_key(|x| x.id);
-            result.push(DiscussionWithPosts {
-                discussion,
-                posts: posts.clone(),
-            });
-        }
-        result
+            .map(|x| (x.id, x))
+            .collect()
     }
-    pub async fn find_by_id_extended(id: u64, pool: &SqlitePool) -> Option<DiscussionExtended> {
-        Self::find_by_id(id, pool).await.map(|mut x| {
-            x.posts.sort_by_key(|t| t.id);
-            let last_posted_at = x.posts.last().map(|t| t.created_at).unwrap_or_default();
-            DiscussionExtended {
-                discussion: x,
-                last_posted_at,
-            }
-        })
+    /// The discussions matching `filter` ordered by id, read as the stream is consumed.
+    pub fn stream_by_filter<'a>(
+        filter: &ExportFilter,
+        pool: &'a SqlitePool,
+    ) -> BoxStream<'a, Result<Discussion, sqlx::Error>> {
+        filter
+            .bind_to(query_as(EXPORT_DISCUSSIONS_SQL.as_str()))
+            .fetch(pool)
+    }
+    pub async fn find_by_id_extended(
+        id: u64,
+        include_removed: bool,
+        pool: &SqlitePool,
+    ) -> Option<Discussion> {
+        let mut discussion = query_as::<_, Discussion>(r"select * from discussions where id=?")
+            .bind(id as i64)
+            .fetch_optional(pool)
+            .await
+            .unwrap()?;
+        if !include_removed && discussion.is_removed() {
+            return None;
+        }
+        let mut posts = Post::find_by_discussion_id(id, pool).await;
+        if !include_removed {
+            posts.retain(|t| !t.is_removed());
+        }
+        posts.sort_by_key(|t| t.id);
+        Post::load_mentions(id, &mut posts, pool).await;
+        discussion.posts = posts;
+        Some(discussion)
     }
     pub async fn find_by_id(id: u64, pool: &SqlitePool) -> Option<Discussion> {
         let mut discussion = query_as::<_, Discussion>(r"select * from discussions where id=?")
@@ -125,12 +740,56 @@ impl Discussion {
         discussion.posts = Post::find_by_discussion_id(discussion.id, pool).await;
         Some(discussion)
     }
+    /// Removed discussions that `filter` would match with `include_removed`, e.g. to delete what an
+    /// earlier export wrote for them.
+    pub async fn find_removed_ids(filter: &ExportFilter, pool: &SqlitePool) -> Vec<u64> {
+        let filter = ExportFilter {
+            include_removed: true,
+            ..filter.clone()
+        };
+        filter
+            .bind_to(query_as::<_, (i64,)>(EXPORT_REMOVED_IDS_SQL.as_str()))
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| x.0 as u64)
+            .collect()
+    }
+    pub async fn find_live_ids(pool: &SqlitePool) -> Vec<u64> {
+        query_as::<_, (i64,)>(
+            r"select id from discussions where removal_detected_at is null order by id",
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|x| x.0 as u64)
+        .collect()
+    }
+    /// Records that a stored discussion is no longer reachable: 404 means deleted, 403 hidden.
+    pub async fn mark_removed(id: u64, hidden: bool, pool: &SqlitePool) {
+        let sql = if hidden {
+            r"update discussions set hidden_at=?1, removal_detected_at=?1 where id=?2 and removal_detected_at is null"
+        } else {
+            r"update discussions set deleted_at=?1, removal_detected_at=?1 where id=?2 and removal_detected_at is null"
+        };
+        query(sql)
+            .bind(Utc::now().fixed_offset())
+            .bind(id as i64)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
     pub async fn save_with_posts(&self, pool: &SqlitePool) {
-        let mut tx = pool.begin().await.unwrap();
+        // IMMEDIATE takes the write lock up front, so concurrent workers wait on the busy timeout
+        // instead of failing with "database is locked" when the FTS triggers upgrade the lock.
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.unwrap();
         query(
             r#"
-            INSERT INTO discussions (id, user_id, username, user_display_name, title, tags, is_frontpage, created_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO discussions (id, user_id, username, user_display_name, title, tags, is_frontpage, created_at, hidden_at,
+                slug, comment_count, participant_count, last_posted_at, last_post_number, is_sticky, is_locked, is_approved)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT (id) DO UPDATE SET
                 user_id = EXCLUDED.user_id,
                 username = EXCLUDED.username,
@@ -138,7 +797,18 @@ impl Discussion {
                 title = EXCLUDED.title,
                 tags = EXCLUDED.tags,
                 is_frontpage = EXCLUDED.is_frontpage,
-                created_at = EXCLUDED.created_at
+                created_at = EXCLUDED.created_at,
+                hidden_at = EXCLUDED.hidden_at,
+                slug = EXCLUDED.slug,
+                comment_count = EXCLUDED.comment_count,
+                participant_count = EXCLUDED.participant_count,
+                last_posted_at = EXCLUDED.last_posted_at,
+                last_post_number = EXCLUDED.last_post_number,
+                is_sticky = EXCLUDED.is_sticky,
+                is_locked = EXCLUDED.is_locked,
+                is_approved = EXCLUDED.is_approved,
+                deleted_at = NULL,
+                removal_detected_at = NULL
             "#,
         )
             .bind(self.id as i64)
@@ -149,16 +819,26 @@ impl Discussion {
             .bind(serde_json::to_string(&self.tags).unwrap())
             .bind(self.is_frontpage)
             .bind(self.created_at)
+            .bind(self.hidden_at)
+            .bind(&self.slug)
+            .bind(self.comment_count as i64)
+            .bind(self.participant_count as i64)
+            .bind(self.last_posted_at)
+            .bind(self.last_post_number as i64)
+            .bind(self.is_sticky)
+            .bind(self.is_locked)
+            .bind(self.is_approved)
             .execute(&mut *tx)
             .await
             .unwrap();
-        if !self.posts.is_empty() {
+        // 16 variables per post
+        for chunk in self.posts.chunks(MAX_VARIABLES / 16) {
             let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
                 r#"
-            INSERT INTO posts (id, user_id, discussion_id, reply_to_id, username, user_display_name, content, created_at)
+            INSERT INTO posts (id, user_id, discussion_id, reply_to_id, username, user_display_name, content, created_at, edited_at, edited_user_id, hidden_at, number, event_type, event_payload, content_html, source)
             "#,
             );
-            query_builder.push_values(&self.posts, |mut b, post| {
+            query_builder.push_values(chunk, |mut b, post| {
                 b.push_bind(post.id as i64)
                     .push_bind(post.user_id as i64)
                     .push_bind(post.discussion_id as i64)
@@ -166,7 +846,19 @@ impl Discussion {
                     .push_bind(&post.username)
                     .push_bind(&post.user_display_name)
                     .push_bind(&post.content)
-                    .push_bind(post.created_at);
+                    .push_bind(post.created_at)
+                    .push_bind(post.edited_at)
+                    .push_bind(post.edited_user_id as i64)
+                    .push_bind(post.hidden_at)
+                    .push_bind(post.number as i64)
+                    .push_bind(&post.event_type)
+                    .push_bind(
+                        post.event_payload
+                            .as_ref()
+                            .map(|x| serde_json::to_string(x).unwrap()),
+                    )
+                    .push_bind(&post.content_html)
+                    .push_bind(&post.source);
             });
             query_builder.push(
                 r#"
@@ -177,23 +869,554 @@ impl Discussion {
                 username = EXCLUDED.username,
                 user_display_name = EXCLUDED.user_display_name,
                 content = EXCLUDED.content,
-                created_at = EXCLUDED.created_at
+                created_at = EXCLUDED.created_at,
+                edited_at = EXCLUDED.edited_at,
+                edited_user_id = EXCLUDED.edited_user_id,
+                hidden_at = EXCLUDED.hidden_at,
+                number = EXCLUDED.number,
+                event_type = EXCLUDED.event_type,
+                event_payload = EXCLUDED.event_payload,
+                content_html = EXCLUDED.content_html,
+                source = EXCLUDED.source,
+                deleted_at = NULL,
+                removal_detected_at = NULL
+            "#,
+            );
+            query_builder.build().execute(&mut *tx).await.unwrap();
+        }
+        if !self.post_ids.is_empty() {
+            let mut query_builder: QueryBuilder<Sqlite> =
+                QueryBuilder::new("update posts set deleted_at=");
+            let now = Utc::now().fixed_offset();
+            query_builder
+                .push_bind(now)
+                .push(", removal_detected_at=")
+                .push_bind(now)
+                .push(" where discussion_id=")
+                .push_bind(self.id as i64)
+                .push(
+                    " and removal_detected_at is null and id not in (select value from json_each(",
+                )
+                // one JSON parameter, long discussions would exceed SQLite's variable limit
+                .push_bind(serde_json::to_string(&self.post_ids).unwrap())
+                .push("))");
+            query_builder.build().execute(&mut *tx).await.unwrap();
+        }
+        PostMention::replace(&self.posts, &mut tx).await;
+        User::upsert(&self.users, &mut tx).await;
+        Tag::upsert(&self.included_tags, false, &mut tx).await;
+        query("delete from discussion_tags where discussion_id=?")
+            .bind(self.id as i64)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        if !self.tag_ids.is_empty() {
+            let mut query_builder: QueryBuilder<Sqlite> =
+                QueryBuilder::new("INSERT INTO discussion_tags (discussion_id, tag_id) ");
+            query_builder.push_values(&self.tag_ids, |mut b, tag_id| {
+                b.push_bind(self.id as i64).push_bind(*tag_id as i64);
+            });
+            query_builder.push(" ON CONFLICT DO NOTHING");
+            query_builder.build().execute(&mut *tx).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+    }
+}
+
+#[derive(Debug, Clone, Default, FromRow, Serialize)]
+pub struct Tag {
+    pub id: u64,
+    pub name: String,
+    pub slug: String,
+    pub color: Option<String>,
+    pub description: Option<String>,
+    pub parent_id: Option<u64>,
+    pub position: Option<i64>,
+    pub updated_at: chrono::DateTime<FixedOffset>,
+}
+impl Tag {
+    pub async fn find_all(pool: &SqlitePool) -> Vec<Tag> {
+        query_as(r"select * from tags order by position is null, position, id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+    /// The tags stored for a discussion in `discussion_tags`, in tag list order.
+    pub async fn find_by_discussion_id(id: u64, pool: &SqlitePool) -> Vec<Tag> {
+        query_as(
+            r"select t.* from tags t join discussion_tags dt on dt.tag_id = t.id
+            where dt.discussion_id = ? order by t.position is null, t.position, t.id",
+        )
+        .bind(id as i64)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+    /// Saves the complete tag list from `/api/tags`, including parents.
+    pub async fn save_all(tags: &[Tag], pool: &SqlitePool) {
+        let mut conn = pool.acquire().await.unwrap();
+        Self::upsert(tags, true, &mut conn).await;
+    }
+    async fn upsert(tags: &[Tag], with_parent: bool, conn: &mut SqliteConnection) {
+        if tags.is_empty() {
+            return;
+        }
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            r#"
+            INSERT INTO tags (id, name, slug, color, description, parent_id, position, updated_at)
+            "#,
+        );
+        query_builder.push_values(tags, |mut b, tag| {
+            b.push_bind(tag.id as i64)
+                .push_bind(&tag.name)
+                .push_bind(&tag.slug)
+                .push_bind(&tag.color)
+                .push_bind(&tag.description)
+                .push_bind(tag.parent_id.map(|x| x as i64))
+                .push_bind(tag.position)
+                .push_bind(tag.updated_at);
+        });
+        query_builder.push(
+            r#"
+            ON CONFLICT (id) DO UPDATE SET
+                name = EXCLUDED.name,
+                slug = EXCLUDED.slug,
+                color = EXCLUDED.color,
+                description = EXCLUDED.description,
+                position = EXCLUDED.position,
+                updated_at = EXCLUDED.updated_at
+            "#,
+        );
+        if with_parent {
+            query_builder.push(", parent_id = EXCLUDED.parent_id");
+        }
+        query_builder.build().execute(conn).await.unwrap();
+    }
+}
+
+#[derive(Debug, Clone, Default, FromRow, Serialize)]
+pub struct User {
+    pub id: u64,
+    pub username: String,
+    pub display_name: String,
+    pub avatar_url: Option<String>,
+    pub bio: Option<String>,
+    pub joined_at: Option<chrono::DateTime<FixedOffset>>,
+    pub last_seen_at: Option<chrono::DateTime<FixedOffset>>,
+    pub comment_count: Option<u64>,
+    pub discussion_count: Option<u64>,
+    #[sqlx(json(nullable))]
+    pub group_ids: Option<Vec<u64>>,
+    pub updated_at: chrono::DateTime<FixedOffset>,
+}
+impl User {
+    /// Returns username -> id, to resolve user mentions that only carry the profile slug.
+    pub async fn find_ids_by_username(pool: &SqlitePool) -> HashMap<String, u64> {
+        query_as::<_, (String, i64)>(r"select username, id from users")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(username, id)| (username, id as u64))
+            .collect()
+    }
+    pub async fn find_by_ids(ids: &[u64], pool: &SqlitePool) -> HashMap<u64, User> {
+        if ids.is_empty() {
+            return HashMap::new();
+        }
+        let mut query_builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("select * from users where id in (");
+        let mut separated = query_builder.separated(", ");
+        for id in ids {
+            separated.push_bind(*id as i64);
+        }
+        separated.push_unseparated(")");
+        query_builder
+            .build_query_as::<User>()
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.id, x))
+            .collect()
+    }
+    pub async fn find_by_id(id: u64, pool: &SqlitePool) -> Option<User> {
+        query_as(r"select * from users where id=?")
+            .bind(id as i64)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+    pub async fn save_all(users: &[User], pool: &SqlitePool) {
+        let mut conn = pool.acquire().await.unwrap();
+        Self::upsert(users, &mut conn).await;
+    }
+    /// Fields missing from a sparse response (NULL) keep their stored values.
+    async fn upsert(users: &[User], conn: &mut SqliteConnection) {
+        // 11 variables per user
+        for chunk in users.chunks(MAX_VARIABLES / 11) {
+            Self::upsert_chunk(chunk, &mut *conn).await;
+        }
+    }
+    async fn upsert_chunk(users: &[User], conn: &mut SqliteConnection) {
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            r#"
+            INSERT INTO users (id, username, display_name, avatar_url, bio, joined_at, last_seen_at, comment_count, discussion_count, group_ids, updated_at)
+            "#,
+        );
+        query_builder.push_values(users, |mut b, user| {
+            b.push_bind(user.id as i64)
+                .push_bind(&user.username)
+                .push_bind(&user.display_name)
+                .push_bind(&user.avatar_url)
+                .push_bind(&user.bio)
+                .push_bind(user.joined_at)
+                .push_bind(user.last_seen_at)
+                .push_bind(user.comment_count.map(|x| x as i64))
+                .push_bind(user.discussion_count.map(|x| x as i64))
+                .push_bind(
+                    user.group_ids
+                        .as_ref()
+                        .map(|x| serde_json::to_string(x).unwrap()),
+                )
+                .push_bind(user.updated_at);
+        });
+        query_builder.push(
+            r#"
+            ON CONFLICT (id) DO UPDATE SET
+                username = EXCLUDED.username,
+                display_name = EXCLUDED.display_name,
+                avatar_url = COALESCE(EXCLUDED.avatar_url, users.avatar_url),
+                bio = COALESCE(EXCLUDED.bio, users.bio),
+                joined_at = COALESCE(EXCLUDED.joined_at, users.joined_at),
+                last_seen_at = COALESCE(EXCLUDED.last_seen_at, users.last_seen_at),
+                comment_count = COALESCE(EXCLUDED.comment_count, users.comment_count),
+                discussion_count = COALESCE(EXCLUDED.discussion_count, users.discussion_count),
+                group_ids = COALESCE(EXCLUDED.group_ids, users.group_ids),
+                updated_at = EXCLUDED.updated_at
+            "#,
+        );
+        query_builder.build().execute(conn).await.unwrap();
+    }
+}
+
+#[derive(Debug, Clone, Default, FromRow, Serialize)]
+pub struct SearchHit {
+    pub kind: String,
+    pub discussion_id: u64,
+    pub post_id: u64, // 0 for discussion title hits
+    pub title: String,
+    pub snippet: String,
+    pub rank: f64,
+}
+impl SearchHit {
+    /// Each whitespace-separated term is matched as a quoted FTS5 string, so user input
+    /// never gets interpreted as query syntax.
+    fn to_match_expr(q: &str) -> String {
+        q.split_whitespace()
+            .map(|x| format!("\"{}\"", x.replace('"', "\"\"")))
+            .join(" ")
+    }
+    pub async fn search(
+        q: &str,
+        limit: u32,
+        offset: u32,
+        include_removed: bool,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        let expr = Self::to_match_expr(q);
+        if expr.is_empty() {
+            bail!("empty query");
+        }
+        // the trigram index matches nothing for shorter terms
+        if let Some(term) = q.split_whitespace().find(|x| x.chars().count() < 3) {
+            bail!("search term {term:?} is too short, each term needs at least 3 characters");
+        }
+        let hits = query_as(
+            r#"
+            SELECT * FROM (
+                SELECT 'post' AS kind, p.discussion_id, p.id AS post_id, COALESCE(d.title, '') AS title,
+                    snippet(posts_fts, 0, '**', '**', '...', 32) AS snippet, bm25(posts_fts) AS rank
+                FROM posts_fts
+                JOIN posts p ON p.id = posts_fts.rowid
+                LEFT JOIN discussions d ON d.id = p.discussion_id
+                WHERE posts_fts MATCH ?1 AND (?2 OR (p.deleted_at IS NULL AND p.hidden_at IS NULL
+                    AND d.deleted_at IS NULL AND d.hidden_at IS NULL))
+                UNION ALL
+                SELECT 'discussion' AS kind, d.id AS discussion_id, 0 AS post_id, d.title,
+                    snippet(discussions_fts, 0, '**', '**', '...', 32) AS snippet, bm25(discussions_fts) AS rank
+                FROM discussions_fts
+                JOIN discussions d ON d.id = discussions_fts.rowid
+                WHERE discussions_fts MATCH ?1 AND (?2 OR (d.deleted_at IS NULL AND d.hidden_at IS NULL))
+            )
+            ORDER BY rank
+            LIMIT ?3 OFFSET ?4
+            "#,
+        )
+        .bind(&expr)
+        .bind(include_removed)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+        Ok(hits)
+    }
+}
+
+/// A vector stored as a little-endian `f32` BLOB.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Vector(pub Vec<f32>);
+impl From<Vec<u8>> for Vector {
+    fn from(value: Vec<u8>) -> Self {
+        Vector(
+            value
+                .chunks_exact(4)
+                .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+                .collect(),
+        )
+    }
+}
+impl Vector {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, FromRow, Serialize)]
+pub struct Embedding {
+    pub post_id: u64,
+    pub model: String,
+    pub chunk_index: u32,
+    pub content_hash: String,
+    pub content: String,
+    #[sqlx(try_from = "Vec<u8>")]
+    pub vector: Vector,
+    pub created_at: chrono::DateTime<FixedOffset>,
+}
+impl Embedding {
+    /// Returns post id -> content hash of those of `post_ids` already embedded with `model`.
+    pub async fn find_hashes(
+        post_ids: &[u64],
+        model: &str,
+        pool: &SqlitePool,
+    ) -> HashMap<u64, String> {
+        query_as::<_, (i64, String)>(
+            r"select distinct post_id, content_hash from embeddings
+            where model=? and post_id in (select value from json_each(?))",
+        )
+        .bind(model)
+        .bind(serde_json::to_string(post_ids).unwrap())
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(post_id, hash)| (post_id as u64, hash))
+        .collect()
+    }
+    /// Replaces all chunks of a post for the given model.
+    pub async fn replace_for_post(
+        post_id: u64,
+        model: &str,
+        embeddings: &[Embedding],
+        pool: &SqlitePool,
+    ) {
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.unwrap();
+        query(r"delete from embeddings where post_id=? and model=?")
+            .bind(post_id as i64)
+            .bind(model)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        if !embeddings.is_empty() {
+            let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+                r#"
+            INSERT INTO embeddings (post_id, model, chunk_index, content_hash, content, vector, created_at)
             "#,
             );
+            query_builder.push_values(embeddings, |mut b, embedding| {
+                b.push_bind(embedding.post_id as i64)
+                    .push_bind(&embedding.model)
+                    .push_bind(embedding.chunk_index)
+                    .push_bind(&embedding.content_hash)
+                    .push_bind(&embedding.content)
+                    .push_bind(embedding.vector.to_bytes())
+                    .push_bind(embedding.created_at);
+            });
             query_builder.build().execute(&mut *tx).await.unwrap();
         }
         tx.commit().await.unwrap();
     }
 }
 
+#[derive(Debug, Clone, Default, FromRow, Serialize)]
+pub struct SemanticHit {
+    pub discussion_id: u64,
+    pub post_id: u64,
+    pub title: String,
+    pub content: String, // best matching chunk
+    #[serde(skip)]
+    pub chunk_index: i64,
+    #[serde(skip)]
+    pub post_number: u64,
+    #[sqlx(skip)]
+    pub url: String,
+    #[sqlx(skip)]
+    pub score: f32,
+}
+impl SemanticHit {
+    /// Brute-force cosine similarity over every stored chunk of `model`, streamed so that only the
+    /// best chunk of each post is kept in memory.
+    /// Returns the top `k` posts and the top `k` discussions (scored by their best post).
+    pub async fn search(
+        vector: &[f32],
+        model: &str,
+        k: usize,
+        include_removed: bool,
+        pool: &SqlitePool,
+    ) -> (Vec<SemanticHit>, Vec<SemanticHit>) {
+        let mut rows = query_as::<_, (i64, i64, i64, Vec<u8>)>(
+            r#"
+            select e.post_id, p.discussion_id, e.chunk_index, e.vector from embeddings e
+            join posts p on p.id = e.post_id
+            left join discussions d on d.id = p.discussion_id
+            where e.model = ? and length(e.vector) > 0 and (? or (p.deleted_at is null and p.hidden_at is null
+                and d.deleted_at is null and d.hidden_at is null))
+            "#,
+        )
+        .bind(model)
+        .bind(include_removed)
+        .fetch(pool);
+        // post id -> (discussion id, chunk index, score)
+        let mut best_by_post: HashMap<u64, (u64, i64, f32)> = HashMap::new();
+        while let Some((post_id, discussion_id, chunk_index, bytes)) =
+            rows.try_next().await.unwrap()
+        {
+            let score = cosine_similarity(vector, &Vector::from(bytes).0);
+            let entry = best_by_post.entry(post_id as u64).or_insert((
+                discussion_id as u64,
+                chunk_index,
+                f32::MIN,
+            ));
+            if score > entry.2 {
+                *entry = (discussion_id as u64, chunk_index, score);
+            }
+        }
+        let mut best_by_discussion: HashMap<u64, (u64, i64, f32)> = HashMap::new();
+        for (post_id, (discussion_id, chunk_index, score)) in best_by_post.iter() {
+            let entry = best_by_discussion.entry(*discussion_id).or_insert((
+                *post_id,
+                *chunk_index,
+                f32::MIN,
+            ));
+            if *score > entry.2 {
+                *entry = (*post_id, *chunk_index, *score);
+            }
+        }
+        let top_posts = best_by_post
+            .into_iter()
+            .map(|(post_id, (_, chunk_index, score))| (post_id, chunk_index, score))
+            .sorted_by(|a, b| b.2.total_cmp(&a.2))
+            .take(k)
+            .collect::<Vec<_>>();
+        let top_discussions = best_by_discussion
+            .into_values()
+            .sorted_by(|a, b| b.2.total_cmp(&a.2))
+            .take(k)
+            .collect::<Vec<_>>();
+        let chunks = top_posts
+            .iter()
+            .chain(top_discussions.iter())
+            .map(|(post_id, chunk_index, _)| (*post_id, *chunk_index))
+            .unique()
+            .collect::<Vec<_>>();
+        let hits = Self::find_hits(&chunks, model, pool).await;
+        let to_hits = |top: Vec<(u64, i64, f32)>| {
+            top.into_iter()
+                .filter_map(|(post_id, chunk_index, score)| {
+                    let mut hit = hits.get(&(post_id, chunk_index))?.clone();
+                    hit.score = score;
+                    Some(hit)
+                })
+                .collect::<Vec<_>>()
+        };
+        (to_hits(top_posts), to_hits(top_discussions))
+    }
+    /// Returns (post id, chunk index) -> hit without a score, in one query.
+    async fn find_hits(
+        chunks: &[(u64, i64)],
+        model: &str,
+        pool: &SqlitePool,
+    ) -> HashMap<(u64, i64), SemanticHit> {
+        if chunks.is_empty() {
+            return HashMap::new();
+        }
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            r#"
+            select p.discussion_id, e.post_id, e.chunk_index, p.number as post_number, coalesce(d.title, '') as title,
+                e.content from embeddings e
+            join posts p on p.id = e.post_id
+            left join discussions d on d.id = p.discussion_id
+            where e.model = "#,
+        );
+        query_builder.push_bind(model);
+        query_builder.push(" and (e.post_id, e.chunk_index) in (");
+        query_builder.push_values(chunks, |mut b, (post_id, chunk_index)| {
+            b.push_bind(*post_id as i64).push_bind(*chunk_index);
+        });
+        query_builder.push(")");
+        query_builder
+            .build_query_as::<SemanticHit>()
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| ((x.post_id, x.chunk_index), x))
+            .collect()
+    }
+}
+
+/// Key-value store for small pieces of crawler state.
+pub struct Kv;
+impl Kv {
+    pub async fn get(key: &str, pool: &SqlitePool) -> Option<String> {
+        query_scalar(r"select value from kv where key=?")
+            .bind(key)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+    pub async fn set(key: &str, value: &str, pool: &SqlitePool) {
+        query(
+            r#"
+            INSERT INTO kv (key, value, updated_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT (key) DO UPDATE SET
+                value = EXCLUDED.value,
+                updated_at = EXCLUDED.updated_at
+            "#,
+        )
+        .bind(key)
+        .bind(value)
+        .bind(Utc::now().fixed_offset())
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+}
+
 #[derive(Debug, Clone, FromRow)]
 pub struct Job {
     pub entity: String,
     pub entity_id: u64,
     #[sqlx(try_from = "String")]
     pub status: JobStatus,
+    pub attempts: u32,
+    pub first_seen_at: Option<chrono::DateTime<FixedOffset>>,
+    pub last_attempt_at: Option<chrono::DateTime<FixedOffset>>,
+    pub next_eligible_at: Option<chrono::DateTime<FixedOffset>>, // only set for failed or partial jobs
+    pub last_error: Option<String>,
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum JobStatus {
     Failed,
     Partial,
@@ -222,7 +1445,20 @@ impl Display for JobStatus {
         }
     }
 }
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Success | JobStatus::Impossible)
+    }
+}
 impl Job {
+    pub async fn find(entity: &str, entity_id: u64, pool: &SqlitePool) -> Option<Self> {
+        query_as(r"select * from jobs where entity=? and entity_id=?")
+            .bind(entity)
+            .bind(entity_id as i64)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
     pub async fn find_by_entity_status(
         entity: &str,
         status: JobStatus,
@@ -238,17 +1474,165 @@ impl Job {
     pub async fn save(&self, pool: &SqlitePool) {
         query(
             r#"
-            INSERT INTO jobs (entity, entity_id, status)
-            VALUES (?, ?, ?)
+            INSERT INTO jobs (entity, entity_id, status, attempts, first_seen_at, last_attempt_at,
+                next_eligible_at, last_error)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT (entity, entity_id) DO UPDATE SET
-                status = EXCLUDED.status
+                status = EXCLUDED.status,
+                attempts = EXCLUDED.attempts,
+                first_seen_at = COALESCE(jobs.first_seen_at, EXCLUDED.first_seen_at),
+                last_attempt_at = EXCLUDED.last_attempt_at,
+                next_eligible_at = EXCLUDED.next_eligible_at,
+                last_error = EXCLUDED.last_error
             "#,
         )
         .bind(&self.entity)
         .bind(self.entity_id as i64)
         .bind(self.status.to_string())
+        .bind(self.attempts)
+        .bind(self.first_seen_at)
+        .bind(self.last_attempt_at)
+        .bind(self.next_eligible_at)
+        .bind(&self.last_error)
         .execute(pool)
         .await
         .unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{get_connection_pool, migrate};
+
+    async fn memory_pool(name: &str) -> SqlitePool {
+        let pool =
+            get_connection_pool(format!("sqlite:file:{name}?mode=memory&cache=shared").as_str())
+                .await
+                .unwrap();
+        migrate(&pool).await.unwrap();
+        pool
+    }
+
+    async fn pool_with_discussions() -> SqlitePool {
+        let pool = memory_pool("discussion_cursor").await;
+        // 2 has no posts, 3 is deleted, 4 has a hidden post; post ids do not follow discussion ids
+        query(
+            r"insert into discussions (id, user_id, username, user_display_name, title, tags, is_frontpage, created_at, deleted_at)
+            values (1, 1, 'a', 'A', 'one', '[]', 1, '2024-01-01T00:00:00Z', null),
+                (2, 1, 'a', 'A', 'two', '[]', 1, '2024-01-02T00:00:00Z', null),
+                (3, 1, 'a', 'A', 'three', '[]', 1, '2024-01-03T00:00:00Z', '2024-02-01T00:00:00Z'),
+                (4, 1, 'a', 'A', 'four', '[]', 1, '2024-01-04T00:00:00Z', null)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        query(
+            r"insert into posts (id, user_id, discussion_id, reply_to_id, username, user_display_name, content, created_at, hidden_at)
+            values (50, 1, 1, 0, 'a', 'A', 'x', '2024-01-01T00:00:00Z', null),
+                (10, 1, 4, 0, 'a', 'A', 'x', '2024-01-04T00:00:00Z', null),
+                (20, 1, 1, 0, 'a', 'A', 'x', '2024-01-01T00:00:00Z', null),
+                (30, 1, 3, 0, 'a', 'A', 'x', '2024-01-03T00:00:00Z', null),
+                (40, 1, 4, 0, 'a', 'A', 'x', '2024-01-04T00:00:00Z', '2024-02-01T00:00:00Z'),
+                (60, 1, 4, 0, 'a', 'A', 'x', '2024-01-04T00:00:00Z', null)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool
+    }
+
+    async fn read_all(filter: &ExportFilter, pool: &SqlitePool) -> (Vec<(u64, Vec<u64>)>, usize) {
+        let mut cursor = DiscussionCursor::new(filter, pool);
+        let mut discussions = vec![];
+        while let Some(x) = cursor.next().await {
+            discussions.push((x.discussion.id, x.posts.iter().map(|t| t.id).collect()));
+        }
+        (discussions, cursor.skipped)
+    }
+
+    #[test]
+    fn query_datetime_accepts_an_offset_with_a_decoded_plus() {
+        let expected = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00+08:00").unwrap();
+        for query in [
+            "created_after=2024-01-01T00:00:00%2B08:00",
+            "created_after=2024-01-01T00:00:00+08:00",
+        ] {
+            let filter = actix_web::web::Query::<DiscussionFilter>::from_query(query).unwrap();
+            assert_eq!(filter.created_after, Some(expected), "{query}");
+        }
+        let filter = actix_web::web::Query::<DiscussionFilter>::from_query(
+            "created_before=2024-01-01T00:00:00Z",
+        )
+        .unwrap();
+        assert_eq!(
+            filter.created_before,
+            Some(expected + chrono::Duration::hours(8))
+        );
+        assert_eq!(filter.created_after, None);
+        assert!(parse_query_datetime("2024-01-01T00:00:00 -08:00").is_err());
+        assert!(
+            actix_web::web::Query::<DiscussionFilter>::from_query("created_after=soon").is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn save_with_posts_splits_inserts_over_the_variable_limit() {
+        let pool = memory_pool("large_discussion").await;
+        let count = 3000;
+        let discussion = Discussion {
+            id: 1,
+            title: "large".to_string(),
+            created_at: chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap(),
+            posts: (1..=count)
+                .map(|id| Post {
+                    id,
+                    user_id: id,
+                    discussion_id: 1,
+                    number: id,
+                    mentioned_post_ids: vec![1, 2, 3, 4],
+                    mentioned_user_ids: vec![1, 2, 3, 4],
+                    ..Default::default()
+                })
+                .collect(),
+            users: (1..=count)
+                .map(|id| User {
+                    id,
+                    username: format!("user{id}"),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        discussion.save_with_posts(&pool).await;
+        for (table, expected) in [
+            ("posts", count),
+            ("users", count),
+            ("post_mentions", count * 8),
+        ] {
+            let total: i64 = query_scalar(format!("select count(*) from {table}").as_str())
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(total as u64, expected, "{table}");
+        }
+    }
+
+    #[tokio::test]
+    async fn discussion_cursor_merges_posts_into_their_discussions() {
+        let pool = pool_with_discussions().await;
+        let (discussions, skipped) = read_all(&ExportFilter::default(), &pool).await;
+        assert_eq!(discussions, vec![(1, vec![20, 50]), (4, vec![10, 60])]);
+        assert_eq!(skipped, 1);
+        let filter = ExportFilter {
+            include_removed: true,
+            ..Default::default()
+        };
+        let (discussions, skipped) = read_all(&filter, &pool).await;
+        assert_eq!(
+            discussions,
+            vec![(1, vec![20, 50]), (3, vec![30]), (4, vec![10, 40, 60])]
+        );
+        assert_eq!(skipped, 1);
+    }
+}
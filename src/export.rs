@@ -0,0 +1,664 @@
+use crate::config::ExportConfig;
+use crate::entity::{Discussion, ExportFilter, Post, PostRevision, Tag, User};
+use anyhow::Context;
+use arrow::array::{
+    ArrayRef, BooleanBuilder, ListBuilder, RecordBatch, StringBuilder, TimestampMillisecondBuilder,
+    UInt64Builder,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+use chrono::{DateTime, FixedOffset};
+use clap::{Args, ValueEnum};
+use futures::TryStreamExt;
+use futures::stream::BoxStream;
+use minijinja::{Environment, ErrorKind};
+use parquet::arrow::AsyncArrowWriter;
+use parquet::basic::ZstdLevel;
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::fs::{File, read_to_string};
+use tokio::io::{AsyncWrite, BufWriter, stdout};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    /// One Markdown file per discussion under `export/`, laid out by `export.template`
+    Markdown,
+    /// One JSON object per line
+    Jsonl,
+    /// `discussions.parquet` and `posts.parquet` in the output directory
+    Parquet,
+}
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExportUnit {
+    /// A discussion with its posts
+    Discussion,
+    /// A single post
+    Post,
+}
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+#[derive(Debug, Clone, Args)]
+pub struct ExportArgs {
+    #[arg(short, long, default_value_t = 2)]
+    pub seg_digit: u32,
+    /// Also export deleted or hidden discussions and posts
+    #[arg(long)]
+    pub include_removed: bool,
+    #[arg(short, long, value_enum, default_value_t = ExportFormat::Markdown)]
+    pub format: ExportFormat,
+    /// File to write JSON Lines to, stdout by default; directory for Parquet, `export` by default
+    #[arg(short, long)]
+    pub output: Option<String>,
+    /// Whether each JSON line is a discussion or a post
+    #[arg(long, value_enum, default_value_t = ExportUnit::Discussion)]
+    pub per: ExportUnit,
+    /// Compress the JSON Lines output
+    #[arg(long, value_enum)]
+    pub compress: Option<Compression>,
+    /// Only discussions with this tag (name or slug)
+    #[arg(long)]
+    pub tag: Option<String>,
+    /// Only discussions started by this user
+    #[arg(long)]
+    pub user_id: Option<u64>,
+    /// Only discussions that are (true) or are not (false) on the front page
+    #[arg(long)]
+    pub frontpage: Option<bool>,
+    /// Only discussions created at or after this time (RFC 3339)
+    #[arg(long)]
+    pub created_after: Option<chrono::DateTime<FixedOffset>>,
+    /// Only discussions created before this time (RFC 3339)
+    #[arg(long)]
+    pub created_before: Option<chrono::DateTime<FixedOffset>>,
+    #[arg(long)]
+    pub min_id: Option<u64>,
+    #[arg(long)]
+    pub max_id: Option<u64>,
+    /// Only discussions that changed since the previous export with this flag to the same format
+    /// and output
+    #[arg(long)]
+    pub since_last_export: bool,
+}
+impl ExportArgs {
+    pub fn filter(&self) -> ExportFilter {
+        ExportFilter {
+            include_removed: self.include_removed,
+            tag: self.tag.clone(),
+            user_id: self.user_id,
+            is_frontpage: self.frontpage,
+            created_after: self.created_after,
+            created_before: self.created_before,
+            min_id: self.min_id,
+            max_id: self.max_id,
+            changed_since: None,
+        }
+    }
+    /// Kv key of the time the previous `--since-last-export` run started.
+    pub fn watermark_key(&self) -> String {
+        format!(
+            "export_watermark:{:?}:{}",
+            self.format,
+            self.output.as_deref().unwrap_or_default()
+        )
+    }
+}
+pub type Output = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// Opens `path`, or stdout for `None` and `-`, optionally compressed. Call `shutdown` when done so
+/// that the compressed stream is finished.
+pub async fn open_output(
+    path: Option<&str>,
+    compression: Option<Compression>,
+) -> anyhow::Result<Output> {
+    let inner: Output = match path {
+        None | Some("-") => Box::new(stdout()),
+        Some(path) => Box::new(File::create(path).await?),
+    };
+    let inner = BufWriter::new(inner);
+    Ok(match compression {
+        None => Box::new(inner),
+        Some(Compression::Gzip) => Box::new(GzipEncoder::new(inner)),
+        Some(Compression::Zstd) => Box::new(ZstdEncoder::new(inner)),
+    })
+}
+
+/// The layout used when `export.template` is not set.
+const DEFAULT_TEMPLATE: &str = include_str!("../templates/discussion.md.j2");
+/// Same as the `Display` of a `DateTime<FixedOffset>`.
+const DEFAULT_DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f %:z";
+
+/// What the template of the Markdown export is rendered with, once per discussion.
+#[derive(Serialize)]
+pub struct TemplateContext<'a> {
+    pub base_url: &'a str,
+    pub discussion: &'a Discussion,
+    pub posts: Vec<TemplatePost<'a>>,
+    pub users: HashMap<u64, User>, // post authors and mentioned users, if crawled
+    pub tags: Vec<Tag>,            // of the discussion, by id through `discussion_tags`
+}
+#[derive(Serialize)]
+pub struct TemplatePost<'a> {
+    #[serde(flatten)]
+    pub post: &'a Post,
+    pub event: Option<String>, // see `Post::event_description`
+    pub revisions: &'a [PostRevision],
+}
+
+/// Renders discussions with the template of `export.template`, or with the built-in one.
+pub struct DiscussionTemplate {
+    env: Environment<'static>,
+    name: String,
+}
+impl DiscussionTemplate {
+    pub async fn new(config: &ExportConfig) -> anyhow::Result<Self> {
+        let (name, source) = match &config.template {
+            Some(path) => (
+                path.clone(),
+                read_to_string(path)
+                    .await
+                    .with_context(|| format!("failed to read template {path}"))?,
+            ),
+            None => ("discussion.md.j2".to_string(), DEFAULT_TEMPLATE.to_string()),
+        };
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.add_filter("datetime", datetime);
+        // the extension of `name` decides whether output is HTML-escaped
+        env.add_template_owned(name.clone(), source)?;
+        Ok(Self { env, name })
+    }
+    pub fn render(&self, context: &TemplateContext) -> anyhow::Result<String> {
+        Ok(self.env.get_template(self.name.as_str())?.render(context)?)
+    }
+}
+/// `{{ post.created_at | datetime("%Y-%m-%d") }}`, with chrono's format syntax.
+fn datetime(value: &str, format: Option<&str>) -> Result<String, minijinja::Error> {
+    let invalid = |detail: String| minijinja::Error::new(ErrorKind::InvalidOperation, detail);
+    let value = DateTime::parse_from_rfc3339(value)
+        .map_err(|err| invalid(format!("invalid datetime {value}: {err}")))?;
+    let format = format.unwrap_or(DEFAULT_DATETIME_FORMAT);
+    let mut formatted = String::new();
+    write!(formatted, "{}", value.format(format))
+        .map_err(|_| invalid(format!("invalid datetime format {format}")))?;
+    Ok(formatted)
+}
+
+const ROW_GROUP_SIZE: usize = 65_536;
+
+/// Arrow column builders for one Parquet file, filled row by row.
+pub trait Columns<T>: Default {
+    fn schema() -> SchemaRef;
+    fn push(&mut self, row: &T);
+    /// Takes the rows pushed so far as a batch, leaving the builders empty.
+    fn finish(&mut self) -> Vec<ArrayRef>;
+}
+
+/// Writes `rows` to a zstd-compressed Parquet file, one row group per `ROW_GROUP_SIZE` rows, so that
+/// at most one row group is held in memory. Returns the number of rows.
+pub async fn write_parquet<T, C: Columns<T>>(
+    path: &str,
+    mut rows: BoxStream<'_, Result<T, sqlx::Error>>,
+) -> anyhow::Result<usize> {
+    let properties = WriterProperties::builder()
+        .set_compression(parquet::basic::Compression::ZSTD(ZstdLevel::default()))
+        .set_max_row_group_size(ROW_GROUP_SIZE)
+        .build();
+    let schema = C::schema();
+    let mut writer =
+        AsyncArrowWriter::try_new(File::create(path).await?, schema.clone(), Some(properties))?;
+    let mut columns = C::default();
+    let (mut total, mut pending) = (0, 0);
+    while let Some(row) = rows.try_next().await? {
+        columns.push(&row);
+        pending += 1;
+        if pending == ROW_GROUP_SIZE {
+            writer
+                .write(&RecordBatch::try_new(schema.clone(), columns.finish())?)
+                .await?;
+            total += pending;
+            pending = 0;
+        }
+    }
+    if pending > 0 {
+        writer
+            .write(&RecordBatch::try_new(schema.clone(), columns.finish())?)
+            .await?;
+        total += pending;
+    }
+    writer.close().await?;
+    Ok(total)
+}
+
+fn utc_millis() -> DataType {
+    DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+}
+fn timestamp_builder() -> TimestampMillisecondBuilder {
+    TimestampMillisecondBuilder::new().with_timezone("UTC")
+}
+fn millis(time: &chrono::DateTime<FixedOffset>) -> i64 {
+    time.timestamp_millis()
+}
+
+pub struct DiscussionColumns {
+    id: UInt64Builder,
+    user_id: UInt64Builder,
+    username: StringBuilder,
+    user_display_name: StringBuilder,
+    title: StringBuilder,
+    slug: StringBuilder,
+    tags: ListBuilder<StringBuilder>,
+    is_frontpage: BooleanBuilder,
+    is_sticky: BooleanBuilder,
+    is_locked: BooleanBuilder,
+    is_approved: BooleanBuilder,
+    comment_count: UInt64Builder,
+    participant_count: UInt64Builder,
+    last_post_number: UInt64Builder,
+    created_at: TimestampMillisecondBuilder,
+    last_posted_at: TimestampMillisecondBuilder,
+    deleted_at: TimestampMillisecondBuilder,
+    hidden_at: TimestampMillisecondBuilder,
+}
+impl Default for DiscussionColumns {
+    fn default() -> Self {
+        Self {
+            id: Default::default(),
+            user_id: Default::default(),
+            username: Default::default(),
+            user_display_name: Default::default(),
+            title: Default::default(),
+            slug: Default::default(),
+            tags: ListBuilder::new(StringBuilder::new()),
+            is_frontpage: Default::default(),
+            is_sticky: Default::default(),
+            is_locked: Default::default(),
+            is_approved: Default::default(),
+            comment_count: Default::default(),
+            participant_count: Default::default(),
+            last_post_number: Default::default(),
+            created_at: timestamp_builder(),
+            last_posted_at: timestamp_builder(),
+            deleted_at: timestamp_builder(),
+            hidden_at: timestamp_builder(),
+        }
+    }
+}
+impl Columns<Discussion> for DiscussionColumns {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::UInt64, false),
+            Field::new("user_id", DataType::UInt64, false),
+            Field::new("username", DataType::Utf8, false),
+            Field::new("user_display_name", DataType::Utf8, false),
+            Field::new("title", DataType::Utf8, false),
+            Field::new("slug", DataType::Utf8, false),
+            Field::new_list("tags", Field::new_list_field(DataType::Utf8, true), false),
+            Field::new("is_frontpage", DataType::Boolean, false),
+            Field::new("is_sticky", DataType::Boolean, false),
+            Field::new("is_locked", DataType::Boolean, false),
+            Field::new("is_approved", DataType::Boolean, false),
+            Field::new("comment_count", DataType::UInt64, false),
+            Field::new("participant_count", DataType::UInt64, false),
+            Field::new("last_post_number", DataType::UInt64, false),
+            Field::new("created_at", utc_millis(), false),
+            Field::new("last_posted_at", utc_millis(), true),
+            Field::new("deleted_at", utc_millis(), true),
+            Field::new("hidden_at", utc_millis(), true),
+        ]))
+    }
+    fn push(&mut self, discussion: &Discussion) {
+        self.id.append_value(discussion.id);
+        self.user_id.append_value(discussion.user_id);
+        self.username.append_value(&discussion.username);
+        self.user_display_name
+            .append_value(&discussion.user_display_name);
+        self.title.append_value(&discussion.title);
+        self.slug.append_value(&discussion.slug);
+        self.tags
+            .append_value(discussion.tags.iter().map(|x| Some(x.as_str())));
+        self.is_frontpage.append_value(discussion.is_frontpage);
+        self.is_sticky.append_value(discussion.is_sticky);
+        self.is_locked.append_value(discussion.is_locked);
+        self.is_approved.append_value(discussion.is_approved);
+        self.comment_count.append_value(discussion.comment_count);
+        self.participant_count
+            .append_value(discussion.participant_count);
+        self.last_post_number
+            .append_value(discussion.last_post_number);
+        self.created_at.append_value(millis(&discussion.created_at));
+        self.last_posted_at
+            .append_option(discussion.last_posted_at.as_ref().map(millis));
+        self.deleted_at
+            .append_option(discussion.deleted_at.as_ref().map(millis));
+        self.hidden_at
+            .append_option(discussion.hidden_at.as_ref().map(millis));
+    }
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.id.finish()),
+            Arc::new(self.user_id.finish()),
+            Arc::new(self.username.finish()),
+            Arc::new(self.user_display_name.finish()),
+            Arc::new(self.title.finish()),
+            Arc::new(self.slug.finish()),
+            Arc::new(self.tags.finish()),
+            Arc::new(self.is_frontpage.finish()),
+            Arc::new(self.is_sticky.finish()),
+            Arc::new(self.is_locked.finish()),
+            Arc::new(self.is_approved.finish()),
+            Arc::new(self.comment_count.finish()),
+            Arc::new(self.participant_count.finish()),
+            Arc::new(self.last_post_number.finish()),
+            Arc::new(self.created_at.finish()),
+            Arc::new(self.last_posted_at.finish()),
+            Arc::new(self.deleted_at.finish()),
+            Arc::new(self.hidden_at.finish()),
+        ]
+    }
+}
+
+pub struct PostColumns {
+    id: UInt64Builder,
+    discussion_id: UInt64Builder,
+    number: UInt64Builder,
+    user_id: UInt64Builder,
+    username: StringBuilder,
+    user_display_name: StringBuilder,
+    reply_to_id: UInt64Builder,
+    content: StringBuilder,
+    content_html: StringBuilder,
+    source: StringBuilder,
+    event_type: StringBuilder,
+    event_payload: StringBuilder,
+    created_at: TimestampMillisecondBuilder,
+    edited_at: TimestampMillisecondBuilder,
+    edited_user_id: UInt64Builder,
+    deleted_at: TimestampMillisecondBuilder,
+    hidden_at: TimestampMillisecondBuilder,
+}
+impl Default for PostColumns {
+    fn default() -> Self {
+        Self {
+            id: Default::default(),
+            discussion_id: Default::default(),
+            number: Default::default(),
+            user_id: Default::default(),
+            username: Default::default(),
+            user_display_name: Default::default(),
+            reply_to_id: Default::default(),
+            content: Default::default(),
+            content_html: Default::default(),
+            source: Default::default(),
+            event_type: Default::default(),
+            event_payload: Default::default(),
+            created_at: timestamp_builder(),
+            edited_at: timestamp_builder(),
+            edited_user_id: Default::default(),
+            deleted_at: timestamp_builder(),
+            hidden_at: timestamp_builder(),
+        }
+    }
+}
+impl Columns<Post> for PostColumns {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::UInt64, false),
+            Field::new("discussion_id", DataType::UInt64, false),
+            Field::new("number", DataType::UInt64, false),
+            Field::new("user_id", DataType::UInt64, false),
+            Field::new("username", DataType::Utf8, false),
+            Field::new("user_display_name", DataType::Utf8, false),
+            Field::new("reply_to_id", DataType::UInt64, false),
+            Field::new("content", DataType::Utf8, false),
+            Field::new("content_html", DataType::Utf8, true),
+            Field::new("source", DataType::Utf8, true),
+            Field::new("event_type", DataType::Utf8, true),
+            Field::new("event_payload", DataType::Utf8, true), // JSON
+            Field::new("created_at", utc_millis(), false),
+            Field::new("edited_at", utc_millis(), true),
+            Field::new("edited_user_id", DataType::UInt64, false),
+            Field::new("deleted_at", utc_millis(), true),
+            Field::new("hidden_at", utc_millis(), true),
+        ]))
+    }
+    fn push(&mut self, row: &Post) {
+        self.id.append_value(row.id);
+        self.discussion_id.append_value(row.discussion_id);
+        self.number.append_value(row.number);
+        self.user_id.append_value(row.user_id);
+        self.username.append_value(&row.username);
+        self.user_display_name.append_value(&row.user_display_name);
+        self.reply_to_id.append_value(row.reply_to_id);
+        self.content.append_value(&row.content);
+        self.content_html.append_option(row.content_html.as_deref());
+        self.source.append_option(row.source.as_deref());
+        self.event_type.append_option(row.event_type.as_deref());
+        self.event_payload
+            .append_option(row.event_payload.as_ref().map(|x| x.to_string()));
+        self.created_at.append_value(millis(&row.created_at));
+        self.edited_at
+            .append_option(row.edited_at.as_ref().map(millis));
+        self.edited_user_id.append_value(row.edited_user_id);
+        self.deleted_at
+            .append_option(row.deleted_at.as_ref().map(millis));
+        self.hidden_at
+            .append_option(row.hidden_at.as_ref().map(millis));
+    }
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.id.finish()),
+            Arc::new(self.discussion_id.finish()),
+            Arc::new(self.number.finish()),
+            Arc::new(self.user_id.finish()),
+            Arc::new(self.username.finish()),
+            Arc::new(self.user_display_name.finish()),
+            Arc::new(self.reply_to_id.finish()),
+            Arc::new(self.content.finish()),
+            Arc::new(self.content_html.finish()),
+            Arc::new(self.source.finish()),
+            Arc::new(self.event_type.finish()),
+            Arc::new(self.event_payload.finish()),
+            Arc::new(self.created_at.finish()),
+            Arc::new(self.edited_at.finish()),
+            Arc::new(self.edited_user_id.finish()),
+            Arc::new(self.deleted_at.finish()),
+            Arc::new(self.hidden_at.finish()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    fn at(value: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(value).unwrap()
+    }
+
+    fn fixture() -> (Discussion, HashMap<u64, Vec<PostRevision>>) {
+        let post = |id: u64, user_id: u64, content: &str| Post {
+            id,
+            user_id,
+            discussion_id: 1,
+            username: format!("user{user_id}"),
+            user_display_name: format!("User {user_id}"),
+            content: content.to_string(),
+            created_at: at("2024-03-01T12:30:45.250+02:00"),
+            number: id,
+            ..Default::default()
+        };
+        let mut reply = post(2, 8, "Thanks & <welcome>");
+        reply.mentioned_post_ids = vec![1];
+        reply.mentioned_user_ids = vec![7, 9];
+        reply.edited_at = Some(at("2024-03-02T08:00:00Z"));
+        let mut event = post(3, 7, "");
+        event.event_type = Some("discussionRenamed".to_string());
+        event.event_payload = Some(serde_json::json!(["Old", "New"]));
+        let mut removed = post(4, 8, "Spam");
+        removed.mentioned_post_ids = vec![1, 2];
+        removed.deleted_at = Some(at("2024-03-03T00:00:00Z"));
+        let mut first = post(1, 7, "Hello\n\nworld");
+        first.reply_ids = vec![2, 4];
+        let discussion = Discussion {
+            id: 1,
+            title: "New".to_string(),
+            posts: vec![first, reply, event, removed],
+            ..Default::default()
+        };
+        let revision = |id: u64, content: &str, replaced_at: &str| PostRevision {
+            id,
+            post_id: 1,
+            content: content.to_string(),
+            replaced_at: at(replaced_at),
+            ..Default::default()
+        };
+        let revisions = HashMap::from([(
+            1,
+            vec![
+                revision(1, "Helo", "2024-03-01T13:00:00Z"),
+                revision(2, "Hello", "2024-03-01T14:00:00.5+00:00"),
+            ],
+        )]);
+        (discussion, revisions)
+    }
+
+    /// The Markdown export as it was written with `format!` before templates.
+    fn legacy_markdown(
+        discussion: &Discussion,
+        revisions: &HashMap<u64, Vec<PostRevision>>,
+        base_url: &str,
+    ) -> String {
+        let topic_owner_user_id = discussion
+            .posts
+            .first()
+            .cloned()
+            .unwrap_or_default()
+            .user_id;
+        let arr = discussion
+            .posts
+            .iter()
+            .map(|item| {
+                let mut reply_line = match item.mentioned_post_ids.as_slice() {
+                    [] => "".to_string(),
+                    [id] => format!("In response to post id: {id}\n"),
+                    ids => format!("In response to post ids: {}\n", ids.iter().join(", ")),
+                };
+                if !item.mentioned_user_ids.is_empty() {
+                    reply_line.push_str(
+                        format!(
+                            "Mentions user ids: {}\n",
+                            item.mentioned_user_ids.iter().join(", ")
+                        )
+                        .as_str(),
+                    );
+                }
+                if !item.reply_ids.is_empty() {
+                    reply_line.push_str(
+                        format!("Replies: {}\n", item.reply_ids.iter().join(", ")).as_str(),
+                    );
+                }
+                let topic_owner_label = if topic_owner_user_id == item.user_id {
+                    " [Topic Owner]"
+                } else {
+                    ""
+                };
+                let mut edited_line = match item.edited_at {
+                    Some(edited_at) => format!("Edited At: {edited_at}\n"),
+                    None => "".to_string(),
+                };
+                if let Some(deleted_at) = item.deleted_at {
+                    edited_line.push_str(format!("Deleted At: {deleted_at}\n").as_str());
+                }
+                if let Some(hidden_at) = item.hidden_at {
+                    edited_line.push_str(format!("Hidden At: {hidden_at}\n").as_str());
+                }
+                let revisions_section = revisions
+                    .get(&item.id)
+                    .map(|x| {
+                        x.iter()
+                            .enumerate()
+                            .map(|(ix, revision)| {
+                                format!(
+                                    "\n\n### Revision {} (replaced at {})\n{}",
+                                    ix + 1,
+                                    revision.replaced_at,
+                                    revision.content
+                                )
+                            })
+                            .join("")
+                    })
+                    .unwrap_or_default();
+                let content_line = match item.event_description() {
+                    Some(description) => format!("Event: {description}"),
+                    None => format!("Content: {}", item.content),
+                };
+                format!(
+                    "## Post ID: {}\nUser: {} (id: {}){}\n{}Created At: {}\n{}{}{}\n\n---",
+                    item.id,
+                    item.user_display_name,
+                    item.user_id,
+                    topic_owner_label,
+                    reply_line,
+                    item.created_at,
+                    edited_line,
+                    content_line,
+                    revisions_section
+                )
+            })
+            .collect::<Vec<String>>();
+        let removed_label = if discussion.deleted_at.is_some() {
+            " [Deleted]"
+        } else if discussion.hidden_at.is_some() {
+            " [Hidden]"
+        } else {
+            ""
+        };
+        format!(
+            "# {}{}\n\n<{}/d/{}>\n\n===\n\n{}",
+            discussion.title,
+            removed_label,
+            base_url,
+            discussion.id,
+            arr.join("\n\n")
+        )
+    }
+
+    #[tokio::test]
+    async fn default_template_renders_like_the_legacy_export() {
+        let (discussion, revisions) = fixture();
+        let context = TemplateContext {
+            base_url: "https://forum.example",
+            discussion: &discussion,
+            posts: discussion
+                .posts
+                .iter()
+                .map(|post| TemplatePost {
+                    post,
+                    event: post.event_description(),
+                    revisions: revisions
+                        .get(&post.id)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default(),
+                })
+                .collect(),
+            users: HashMap::new(),
+            tags: vec![],
+        };
+        let template = DiscussionTemplate::new(&ExportConfig::default())
+            .await
+            .unwrap();
+        let rendered = template.render(&context).unwrap();
+        assert_eq!(
+            rendered,
+            legacy_markdown(&discussion, &revisions, "https://forum.example")
+        );
+    }
+}
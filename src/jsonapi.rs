@@ -0,0 +1,352 @@
+use chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum JsonApiError {
+    #[error("invalid document at `{path}`: {source}")]
+    Document {
+        path: String,
+        source: serde_json::Error,
+    },
+    #[error("invalid attributes of {kind} {id} at `{path}`: {source}")]
+    Attributes {
+        kind: String,
+        id: String,
+        path: String,
+        source: serde_json::Error,
+    },
+    #[error("missing attribute `{name}` of {kind} {id}")]
+    MissingAttribute {
+        kind: String,
+        id: String,
+        name: &'static str,
+    },
+    #[error("invalid id of {kind}: {id:?}")]
+    Id { kind: String, id: String },
+    #[error("relationship `{name}` of {kind} {id} is not to-one")]
+    NotToOne {
+        kind: String,
+        id: String,
+        name: String,
+    },
+}
+
+/// A Flarum JSON:API document. `D` is `Resource` or `Vec<Resource>`.
+#[derive(Debug, Deserialize)]
+pub struct Document<D> {
+    pub data: D,
+    #[serde(default)]
+    pub included: Vec<Resource>,
+    #[serde(default)]
+    pub links: Links,
+}
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Links {
+    pub next: Option<String>,
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct Resource {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+    #[serde(default)]
+    pub attributes: serde_json::Value,
+    #[serde(default)]
+    pub relationships: HashMap<String, Relationship>,
+}
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Relationship {
+    #[serde(default)]
+    pub data: Option<RelationshipData>,
+}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum RelationshipData {
+    One(ResourceIdentifier),
+    Many(Vec<ResourceIdentifier>),
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResourceIdentifier {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+}
+
+fn parse_id(kind: &str, id: &str) -> Result<u64, JsonApiError> {
+    id.parse::<u64>().map_err(|_| JsonApiError::Id {
+        kind: kind.to_string(),
+        id: id.to_string(),
+    })
+}
+
+impl<D: DeserializeOwned> Document<D> {
+    pub fn parse(bytes: &[u8]) -> Result<Self, JsonApiError> {
+        let deserializer = &mut serde_json::Deserializer::from_slice(bytes);
+        serde_path_to_error::deserialize(deserializer).map_err(|err| JsonApiError::Document {
+            path: err.path().to_string(),
+            source: err.into_inner(),
+        })
+    }
+}
+impl<D> Document<D> {
+    /// Looks up the full resource for a relationship in `included`.
+    pub fn resolve(&self, identifier: &ResourceIdentifier) -> Option<&Resource> {
+        self.included
+            .iter()
+            .find(|x| x.kind == identifier.kind && x.id == identifier.id)
+    }
+    pub fn included_of<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a Resource> {
+        self.included.iter().filter(move |x| x.kind == kind)
+    }
+}
+impl Resource {
+    pub fn id_u64(&self) -> Result<u64, JsonApiError> {
+        parse_id(self.kind.as_str(), self.id.as_str())
+    }
+    pub fn attributes<A: DeserializeOwned>(&self) -> Result<A, JsonApiError> {
+        serde_path_to_error::deserialize(&self.attributes).map_err(|err| JsonApiError::Attributes {
+            kind: self.kind.to_string(),
+            id: self.id.to_string(),
+            path: err.path().to_string(),
+            source: err.into_inner(),
+        })
+    }
+    /// Returns `None` when the relationship is absent or null, e.g. for a deleted user.
+    pub fn to_one(&self, name: &str) -> Result<Option<&ResourceIdentifier>, JsonApiError> {
+        match self.relationships.get(name).and_then(|x| x.data.as_ref()) {
+            None => Ok(None),
+            Some(RelationshipData::One(identifier)) => Ok(Some(identifier)),
+            Some(RelationshipData::Many(_)) => Err(JsonApiError::NotToOne {
+                kind: self.kind.to_string(),
+                id: self.id.to_string(),
+                name: name.to_string(),
+            }),
+        }
+    }
+    /// Whether the relationship carries resource linkage, as opposed to being absent or links-only.
+    pub fn has_data(&self, name: &str) -> bool {
+        self.relationships
+            .get(name)
+            .is_some_and(|x| x.data.is_some())
+    }
+    pub fn to_many(&self, name: &str) -> &[ResourceIdentifier] {
+        match self.relationships.get(name).and_then(|x| x.data.as_ref()) {
+            Some(RelationshipData::Many(identifiers)) => identifiers.as_slice(),
+            Some(RelationshipData::One(identifier)) => std::slice::from_ref(identifier),
+            None => &[],
+        }
+    }
+    /// Id of a to-one relationship, 0 when it is absent or null.
+    pub fn to_one_id(&self, name: &str) -> Result<u64, JsonApiError> {
+        match self.to_one(name)? {
+            Some(identifier) => identifier.id_u64(),
+            None => Ok(0),
+        }
+    }
+}
+impl ResourceIdentifier {
+    pub fn id_u64(&self) -> Result<u64, JsonApiError> {
+        parse_id(self.kind.as_str(), self.id.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscussionListAttributes {
+    pub comment_count: u64,
+    pub last_posted_at: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    pub is_sticky: bool,
+}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscussionAttributes {
+    pub title: String,
+    pub slug: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub frontpage: bool,
+    pub hidden_at: Option<DateTime<FixedOffset>>,
+    pub comment_count: u64,
+    pub participant_count: u64,
+    pub last_posted_at: Option<DateTime<FixedOffset>>,
+    pub last_post_number: Option<u64>,
+    // Attributes of bundled extensions that may be disabled
+    #[serde(default)]
+    pub is_sticky: bool,
+    #[serde(default)]
+    pub is_locked: bool,
+    #[serde(default = "default_true")]
+    pub is_approved: bool,
+}
+fn default_true() -> bool {
+    true
+}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostAttributes {
+    pub number: u64,
+    pub content_type: String,
+    pub content: Option<serde_json::Value>, // the payload of event posts
+    pub content_html: Option<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub edited_at: Option<DateTime<FixedOffset>>,
+    pub hidden_at: Option<DateTime<FixedOffset>>,
+}
+/// What is needed of a post included with its discussion to tell whether the stored copy is stale.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostEditAttributes {
+    pub edited_at: Option<DateTime<FixedOffset>>,
+}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserAttributes {
+    pub username: String,
+    pub display_name: String,
+    pub avatar_url: Option<String>,
+    pub bio: Option<String>,
+    pub join_time: Option<DateTime<FixedOffset>>,
+    pub last_seen_at: Option<DateTime<FixedOffset>>,
+    pub comment_count: Option<u64>,
+    pub discussion_count: Option<u64>,
+}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagAttributes {
+    pub name: String,
+    pub slug: String,
+    pub color: Option<String>,
+    pub description: Option<String>,
+    pub position: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISCUSSION: &str = r#"{
+        "data": {
+            "type": "discussions",
+            "id": "1",
+            "attributes": {"title": "Hello"},
+            "relationships": {
+                "user": {"data": {"type": "users", "id": "2"}},
+                "lastPostedUser": {"data": null},
+                "firstPost": {"links": {"related": "https://example.com"}},
+                "posts": {"data": [{"type": "posts", "id": "10"}, {"type": "posts", "id": "11"}]}
+            }
+        },
+        "included": [
+            {"type": "users", "id": "2", "attributes": {"username": "alice", "displayName": "Alice"}},
+            {"type": "posts", "id": "10", "attributes": {"editedAt": null}},
+            {"type": "tags", "id": "2", "attributes": {"name": "General", "slug": "general"}}
+        ]
+    }"#;
+
+    fn parse() -> Document<Resource> {
+        Document::parse(DISCUSSION.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn resolves_included_resources_by_type_and_id() {
+        let document = parse();
+        let user = document
+            .resolve(document.data.to_one("user").unwrap().unwrap())
+            .unwrap();
+        let attributes: UserAttributes = user.attributes().unwrap();
+        assert_eq!(attributes.username, "alice");
+        assert_eq!(attributes.display_name, "Alice");
+        // same id, other type
+        let tags = document.included_of("tags").collect::<Vec<_>>();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(
+            tags[0].attributes::<TagAttributes>().unwrap().name,
+            "General"
+        );
+        // listed but not included
+        assert!(
+            document
+                .resolve(&document.data.to_many("posts")[1])
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn missing_and_null_relationships() {
+        let document = parse();
+        let data = &document.data;
+        assert!(data.to_one("lastPostedUser").unwrap().is_none());
+        assert_eq!(data.to_one_id("lastPostedUser").unwrap(), 0);
+        assert!(data.to_one("recipientUsers").unwrap().is_none());
+        assert_eq!(data.to_one_id("recipientUsers").unwrap(), 0);
+        assert!(data.to_many("tags").is_empty());
+        assert!(data.has_data("posts"));
+        assert!(!data.has_data("firstPost")); // links only
+        assert_eq!(
+            data.to_many("posts")
+                .iter()
+                .map(|x| x.id_u64().unwrap())
+                .collect::<Vec<_>>(),
+            vec![10, 11]
+        );
+        assert!(matches!(
+            data.to_one("posts"),
+            Err(JsonApiError::NotToOne { name, .. }) if name == "posts"
+        ));
+    }
+
+    #[test]
+    fn malformed_document_reports_the_path() {
+        let err = Document::<Resource>::parse(
+            br#"{"data": {"type": "discussions", "id": 1, "attributes": {}}}"#,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("invalid document at `data.id`: "),
+            "{err}"
+        );
+        let err =
+            Document::<Vec<Resource>>::parse(br#"{"data": [{"type": "users"}]}"#).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("invalid document at `data[0]`: missing field `id`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn malformed_attributes_report_the_resource_and_path() {
+        let document = parse();
+        let err = document
+            .data
+            .attributes::<DiscussionAttributes>()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid attributes of discussions 1 at `.`: missing field `slug`"
+        );
+        let resource: Resource = serde_json::from_str(
+            r#"{"type": "posts", "id": "3", "attributes": {"editedAt": "yesterday"}}"#,
+        )
+        .unwrap();
+        let err = resource.attributes::<PostEditAttributes>().unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("invalid attributes of posts 3 at `editedAt`: "),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn invalid_ids() {
+        let resource: Resource = serde_json::from_str(r#"{"type": "users", "id": "abc"}"#).unwrap();
+        assert_eq!(
+            resource.id_u64().unwrap_err().to_string(),
+            r#"invalid id of users: "abc""#
+        );
+    }
+}
@@ -1,18 +1,26 @@
 use crate::cmd::Cmd;
 use crate::config::Config;
-use crate::db::get_connection_pool;
+use crate::db::{get_connection_pool, migrate};
+use crate::export::ExportArgs;
 use clap::{Parser, Subcommand};
 use tracing::error;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
 mod api;
+mod assets;
+mod auth;
 mod cmd;
 mod config;
 mod crawler;
 mod db;
+mod embedding;
 mod entity;
+mod export;
+mod jsonapi;
 mod server;
+mod shutdown;
+mod throttle;
 
 #[derive(Parser)]
 struct Cli {
@@ -25,18 +33,45 @@ struct Cli {
 enum SubCmd {
     Cron {
         page: usize,
+        /// Only enqueue discussions with new posts, walking at most `page` pages
+        #[arg(short, long)]
+        incremental: bool,
     },
-    Export {
-        #[arg(short, long, default_value_t = 2)]
-        seg_digit: u32,
-    },
+    Export(ExportArgs),
     Embed,
-    Retry,
+    Retry {
+        /// Ignore the max attempts and backoff window
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Re-crawl every stored discussion to detect deleted or hidden content
+    Recheck,
     Full {
         #[arg(short, long, default_value_t = 1)]
         page_start: usize,
         #[arg(short, long)]
         ignore_existed: bool,
+        /// Continue after the last page completed by a previous run, ignoring `page_start`
+        #[arg(short, long)]
+        resume: bool,
+    },
+    Migrate,
+    /// Crawl the tag list with its hierarchy from /api/tags
+    Tags,
+    /// Crawl every user profile from /api/users (needs permission to view the user list)
+    Users,
+    /// Regenerate the Markdown of stored posts from their raw HTML, offline
+    Reconvert,
+    /// Download the images and attachments of stored posts (needs `assets` in the config)
+    Assets {
+        /// Also retry assets whose download failed before
+        #[arg(short, long)]
+        retry_failed: bool,
+    },
+    Search {
+        query: String,
+        #[arg(short, long, default_value_t = 20)]
+        limit: u32,
     },
     Server {
         #[arg(short, long, default_value = "0.0.0.0")]
@@ -52,27 +87,60 @@ async fn main() {
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| format!("{}=debug", env!("CARGO_CRATE_NAME")).into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
     let cli = Cli::parse();
     let config_path = cli.config.unwrap_or("config.yml".to_string());
     let config = Config::load(config_path.as_str()).await.unwrap();
+    throttle::init(config.throttle.clone());
     let conn = get_connection_pool(config.db.as_str()).await.unwrap();
+    if (config.auto_migrate || matches!(cli.cmd, SubCmd::Migrate))
+        && let Err(err) = migrate(&conn).await
+    {
+        error!("migrate error: {:#}", err);
+        return;
+    }
     let cmd = Cmd::new(config, conn);
     match cli.cmd {
-        SubCmd::Cron { page } => {
-            if let Err(err) = cmd.cron(page).await {
+        SubCmd::Cron { page, incremental } => {
+            if let Err(err) = cmd.cron(page, incremental).await {
                 error!("cmd.cron error: {:#}", err);
             }
         }
         SubCmd::Full {
             page_start,
             ignore_existed,
+            resume,
         } => {
-            cmd.full(page_start, ignore_existed).await;
+            if let Err(err) = cmd.full(page_start, ignore_existed, resume).await {
+                error!("cmd.full error: {:#}", err);
+            }
         }
-        SubCmd::Retry => {
-            cmd.retry().await;
+        SubCmd::Retry { force } => {
+            cmd.retry(force).await;
+        }
+        SubCmd::Recheck => cmd.recheck().await,
+        SubCmd::Migrate => {}
+        SubCmd::Reconvert => cmd.reconvert().await,
+        SubCmd::Assets { retry_failed } => {
+            if let Err(err) = cmd.assets(retry_failed).await {
+                error!("cmd.assets error: {:#}", err);
+            }
+        }
+        SubCmd::Tags => {
+            if let Err(err) = cmd.tags().await {
+                error!("cmd.tags error: {:#}", err);
+            }
+        }
+        SubCmd::Users => {
+            if let Err(err) = cmd.users().await {
+                error!("cmd.users error: {:#}", err);
+            }
+        }
+        SubCmd::Search { query, limit } => {
+            if let Err(err) = cmd.search(query.as_str(), limit).await {
+                println!("error searching: {err:#}");
+            }
         }
         SubCmd::Server { port, addr } => cmd.server(addr, port).await,
         SubCmd::Embed => {
@@ -80,6 +148,10 @@ async fn main() {
                 println!("error embedding: {err:#}");
             }
         }
-        SubCmd::Export { seg_digit } => cmd.export(seg_digit).await,
+        SubCmd::Export(args) => {
+            if let Err(err) = cmd.export(args).await {
+                error!("cmd.export error: {:#}", err);
+            }
+        }
     }
 }
@@ -1,11 +1,14 @@
 mod service;
 
 use crate::config::Config;
-use crate::server::service::{get_discussion, index};
+use crate::embedding::EmbeddingClient;
+use crate::server::service::{
+    get_discussion, get_post_revisions, get_user, index, list_discussion, list_tags, search,
+    semantic_search,
+};
 use actix_cors::Cors;
 use actix_web::body::BoxBody;
-use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError, web};
-use serde::Serialize;
+use actix_web::{App, HttpResponse, HttpServer, ResponseError, web};
 use serde_json::json;
 use sqlx::SqlitePool;
 use thiserror::Error;
@@ -15,6 +18,7 @@ use tracing::{info, instrument};
 pub struct AppState {
     pub conn: SqlitePool,
     pub config: Config,
+    pub embedding: Option<EmbeddingClient>, // shared by every semantic search request
 }
 
 #[derive(Debug, Error)]
@@ -37,6 +41,12 @@ pub async fn run_server(addr: String, port: u16, state: AppState) {
             .app_data(web::Data::new(state.clone()))
             .service(index)
             .service(get_discussion)
+            .service(list_discussion)
+            .service(get_post_revisions)
+            .service(get_user)
+            .service(list_tags)
+            .service(search)
+            .service(semantic_search)
     })
     .bind((addr, port))
     .unwrap()
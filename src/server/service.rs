@@ -1,26 +1,152 @@
-use crate::entity::Discussion;
+use crate::entity::{
+    Discussion, DiscussionFilter, Post, PostRevision, SearchHit, SemanticHit, Tag, User,
+};
 use crate::server::{AppError, AppState};
 use actix_web::{HttpResponse, Responder, get, web};
 use anyhow::Context;
+use serde::Deserialize;
+use serde_json::json;
 
 #[get("/")]
 async fn index() -> impl Responder {
     "flarum-crawler"
 }
+#[derive(Debug, Deserialize)]
+pub struct RemovedQuery {
+    #[serde(default)]
+    include_removed: bool,
+}
 #[get("/discussion/{id}")]
 pub async fn get_discussion(
     path: web::Path<u64>,
+    removed: web::Query<RemovedQuery>,
     state: web::Data<AppState>,
 ) -> Result<impl Responder, AppError> {
     let id = path.into_inner();
-    let discussion = Discussion::find_by_id_extended(id, &state.conn)
+    let discussion = Discussion::find_by_id_extended(id, removed.include_removed, &state.conn)
         .await
         .context("cannot find discussion")?;
     Ok(HttpResponse::Ok().json(discussion))
 }
 
-pub async fn list_discussion() -> impl Responder {
-    HttpResponse::Ok().finish()
+#[get("/post/{id}/revisions")]
+pub async fn get_post_revisions(
+    path: web::Path<u64>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let revisions = PostRevision::find_by_post_id(path.into_inner(), &state.conn).await;
+    Ok(HttpResponse::Ok().json(revisions))
 }
 
-pub async fn search() {}
+#[get("/user/{id}")]
+pub async fn get_user(
+    path: web::Path<u64>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let user = User::find_by_id(path.into_inner(), &state.conn)
+        .await
+        .context("cannot find user")?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[get("/tags")]
+pub async fn list_tags(state: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    let tags = Tag::find_all(&state.conn).await;
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Pagination {
+    #[serde(default = "default_list_limit")]
+    limit: u32,
+    #[serde(default)]
+    offset: u32,
+}
+fn default_list_limit() -> u32 {
+    20
+}
+#[get("/discussions")]
+pub async fn list_discussion(
+    filter: web::Query<DiscussionFilter>,
+    pagination: web::Query<Pagination>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let limit = pagination.limit.min(100);
+    let (discussions, total) =
+        Discussion::find_extended_by_filter(&filter, limit, pagination.offset, &state.conn).await;
+    Ok(HttpResponse::Ok().json(json!({
+        "total": total,
+        "limit": limit,
+        "offset": pagination.offset,
+        "discussions": discussions,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    #[serde(default = "default_search_limit")]
+    limit: u32,
+    #[serde(default)]
+    offset: u32,
+}
+fn default_search_limit() -> u32 {
+    20
+}
+#[get("/search")]
+pub async fn search(
+    query: web::Query<SearchQuery>,
+    removed: web::Query<RemovedQuery>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let hits = SearchHit::search(
+        query.q.as_str(),
+        query.limit.min(100),
+        query.offset,
+        removed.include_removed,
+        &state.conn,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(hits))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SemanticSearchQuery {
+    q: String,
+    #[serde(default = "default_semantic_search_k")]
+    k: usize,
+}
+fn default_semantic_search_k() -> usize {
+    10
+}
+#[get("/semantic-search")]
+pub async fn semantic_search(
+    query: web::Query<SemanticSearchQuery>,
+    removed: web::Query<RemovedQuery>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let client = state.embedding.as_ref().context("no embedding config")?;
+    let vector = client
+        .embed(&[query.q.to_string()])
+        .await?
+        .pop()
+        .context("no embedding returned")?;
+    let (mut posts, mut discussions) = SemanticHit::search(
+        &vector,
+        client.model(),
+        query.k.min(100),
+        removed.include_removed,
+        &state.conn,
+    )
+    .await;
+    for hit in posts.iter_mut() {
+        hit.url = Post::url_of(&state.config.base_url, hit.discussion_id, hit.post_number);
+    }
+    for hit in discussions.iter_mut() {
+        hit.url = Post::url_of(&state.config.base_url, hit.discussion_id, 0);
+    }
+    Ok(HttpResponse::Ok().json(json!({
+        "posts": posts,
+        "discussions": discussions,
+    })))
+}
@@ -0,0 +1,50 @@
+use tokio::signal::ctrl_c;
+use tokio::sync::watch;
+use tracing::warn;
+
+/// Tracks whether SIGINT or SIGTERM has been received.
+///
+/// The first signal asks producers to stop enqueueing so that workers can finish the discussions
+/// already handed to them. A second signal exits immediately.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+impl Shutdown {
+    pub fn listen() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            let mut requested = false;
+            loop {
+                wait_for_signal().await;
+                if requested {
+                    warn!("Forced shutdown");
+                    std::process::exit(130);
+                }
+                warn!("Shutting down after in-flight discussions, signal again to force");
+                requested = true;
+                sender.send_replace(true);
+            }
+        });
+        Self { receiver }
+    }
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+    pub async fn requested(&self) {
+        _ = self.receiver.clone().wait_for(|x| *x).await;
+    }
+}
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    _ = ctrl_c().await;
+}
@@ -0,0 +1,190 @@
+use crate::config::ThrottleConfig;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{StatusCode, Url};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tokio::time::{Instant, sleep_until};
+use tracing::warn;
+
+/// Paces requests per host and decides how long to back off before retrying a transient error.
+///
+/// Retries are drawn from a budget shared by all workers. Successful requests slowly refill it, so
+/// a forum that keeps failing gets fewer and fewer retries instead of a constant stream of them.
+#[derive(Debug)]
+pub struct Throttle {
+    config: ThrottleConfig,
+    next_slots: Mutex<HashMap<String, Instant>>,
+    budget: Mutex<f64>,
+}
+static THROTTLE: OnceLock<Throttle> = OnceLock::new();
+
+pub fn init(config: ThrottleConfig) {
+    _ = THROTTLE.set(Throttle::new(config));
+}
+pub fn get_throttle() -> &'static Throttle {
+    THROTTLE.get_or_init(|| Throttle::new(ThrottleConfig::default()))
+}
+fn host_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|x| x.host_str().map(|x| x.to_string()))
+        .unwrap_or_default()
+}
+pub fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::GATEWAY_TIMEOUT,
+        ]
+        .contains(&status)
+}
+/// Parses `Retry-After` given either in seconds or as an HTTP date.
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+impl Throttle {
+    fn new(config: ThrottleConfig) -> Self {
+        Self {
+            budget: Mutex::new(config.retry_budget),
+            config,
+            next_slots: Mutex::new(HashMap::new()),
+        }
+    }
+    pub fn max_retries(&self) -> u32 {
+        self.config.max_retries
+    }
+    /// Waits until a request to the host of `url` is allowed by `requests_per_second`.
+    pub async fn acquire(&self, url: &str) {
+        let now = Instant::now();
+        let slot = {
+            let mut next_slots = self.next_slots.lock().unwrap();
+            let next = next_slots.entry(host_of(url)).or_insert(now);
+            let slot = (*next).max(now);
+            *next = match self.config.requests_per_second {
+                rps if rps > 0.0 => slot + Duration::from_secs_f64(1.0 / rps),
+                _ => slot,
+            };
+            slot
+        };
+        sleep_until(slot).await;
+    }
+    /// Holds back every request to the host of `url` for `delay`, e.g. after a `Retry-After`.
+    pub fn pause_host(&self, url: &str, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut next_slots = self.next_slots.lock().unwrap();
+        let next = next_slots.entry(host_of(url)).or_insert(until);
+        *next = (*next).max(until);
+    }
+    pub fn record_success(&self) {
+        let mut budget = self.budget.lock().unwrap();
+        *budget = (*budget + self.config.retry_budget_ratio).min(self.config.retry_budget);
+    }
+    /// Takes one retry from the budget, returning `false` when it is exhausted.
+    pub fn withdraw_retry(&self) -> bool {
+        let mut budget = self.budget.lock().unwrap();
+        if *budget < 1.0 {
+            warn!("Retry budget exhausted, not retrying");
+            return false;
+        }
+        *budget -= 1.0;
+        true
+    }
+    /// Exponential backoff with jitter for the zero-based `attempt`; a server-provided
+    /// `retry_after` takes precedence when it is longer.
+    pub fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let base = self.config.backoff_base_ms as f64;
+        let max = self.config.backoff_max_ms as f64;
+        let exp = (base * 2f64.powi(attempt.min(30) as i32)).min(max);
+        let delay = Duration::from_millis((exp / 2.0 + fastrand::f64() * exp / 2.0) as u64);
+        match retry_after {
+            Some(retry_after) => retry_after.max(delay),
+            None => delay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn retry_after(value: &str) -> Option<Duration> {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        parse_retry_after(&headers)
+    }
+    fn throttle(backoff_base_ms: u64, backoff_max_ms: u64) -> Throttle {
+        Throttle::new(ThrottleConfig {
+            backoff_base_ms,
+            backoff_max_ms,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(" 0 "), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_as_http_date() {
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(60));
+        let delay = retry_after(date.as_str()).unwrap();
+        // the date has whole seconds
+        assert!(
+            delay > Duration::from_secs(58) && delay <= Duration::from_secs(60),
+            "{delay:?}"
+        );
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn retry_after_garbage() {
+        assert_eq!(retry_after("soon"), None);
+        assert_eq!(retry_after("-5"), None);
+        assert_eq!(retry_after("1.5"), None);
+        assert_eq!(parse_retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn backoff_jitter_stays_within_half_to_full_delay() {
+        let throttle = throttle(100, 60_000);
+        for (attempt, full) in [(0, 100), (1, 200), (3, 800)] {
+            for _ in 0..200 {
+                let delay = throttle.backoff(attempt, None).as_millis() as u64;
+                assert!((full / 2..=full).contains(&delay), "{attempt}: {delay}");
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let throttle = throttle(500, 2_000);
+        for attempt in [3, 10, 31, u32::MAX] {
+            let delay = throttle.backoff(attempt, None).as_millis() as u64;
+            assert!((1_000..=2_000).contains(&delay), "{attempt}: {delay}");
+        }
+    }
+
+    #[test]
+    fn backoff_honors_a_longer_retry_after() {
+        let throttle = throttle(100, 1_000);
+        let long = Duration::from_secs(30);
+        assert_eq!(throttle.backoff(0, Some(long)), long);
+        let short = Duration::from_millis(1);
+        assert!(throttle.backoff(0, Some(short)) >= Duration::from_millis(50));
+    }
+}